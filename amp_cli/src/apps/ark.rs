@@ -0,0 +1,134 @@
+use crate::apps::SubApp;
+use amp_lib::ark::*;
+use clap::{Parser, Subcommand};
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct ArkApp {
+    #[command(subcommand)]
+    pub commands: ArkCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ArkCommand {
+    #[command(name = "list", about = "List files in .hdr/.ark archive")]
+    List(ArkListApp),
+    #[command(name = "extract", about = "Extract files from .hdr/.ark archive")]
+    Extract(ArkExtractApp),
+    #[command(name = "pack", about = "Build .hdr/.ark archive from directory, or rebuild archive with replacement files")]
+    Pack(ArkPackApp),
+}
+
+#[derive(Parser, Debug)]
+pub struct ArkListApp {
+    #[arg(help = "Path to archive header (.hdr) or disc image (.iso), .ark parts are read from same directory", required = true)]
+    pub hdr_path: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct ArkExtractApp {
+    #[arg(help = "Path to archive header (.hdr) or disc image (.iso), .ark parts are read from same directory", required = true)]
+    pub hdr_path: String,
+    #[arg(help = "Path to output directory", required = true)]
+    pub output_path: String,
+    #[arg(help = "Files or directories in archive to extract (extracts everything if not set)")]
+    pub filters: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct ArkPackApp {
+    #[arg(help = "Path to directory of files, or archive header (.hdr) or disc image (.iso) to rebuild", required = true)]
+    pub input_path: String,
+    #[arg(help = "Path to output archive header (.hdr), .ark parts are written next to it", required = true)]
+    pub output_path: String,
+    #[arg(short, long, help = "Directory of files to add or replace, paths relative to directory match paths in archive")]
+    pub replace_path: Option<String>,
+}
+
+impl SubApp for ArkApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.commands {
+            ArkCommand::List(app) => app.process(),
+            ArkCommand::Extract(app) => app.process(),
+            ArkCommand::Pack(app) => app.process(),
+        }
+    }
+}
+
+impl SubApp for ArkListApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let ark = Ark::open(&self.hdr_path)?;
+
+        println!("{:>6}  {:>10} {:>4} {:>10}  Path", "#", "Size", "Part", "Offset");
+
+        for (i, entry) in ark.entries.iter().enumerate() {
+            println!("{:>6}  {:>10} {:>4} {:>#10x}  {}", i, entry.size, entry.part, entry.part_offset, entry.path());
+        }
+
+        let total_size = ark.entries
+            .iter()
+            .map(|e| e.size as u64)
+            .sum::<u64>();
+
+        println!("{} files in {} parts, {} bytes total (version {})", ark.entries.len(), ark.part_sizes.len(), total_size, ark.version);
+
+        Ok(())
+    }
+}
+
+impl SubApp for ArkExtractApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let output_path = Path::new(&self.output_path);
+        let ark = Ark::open(&self.hdr_path)?;
+
+        let filters = self.filters
+            .iter()
+            .map(|f| f.replace('\\', "/").trim_matches('/').to_lowercase())
+            .collect::<Vec<_>>();
+
+        let mut file_count = 0;
+        let mut total_size = 0;
+
+        // Matches whole path or directory it's in
+        for entry in ark.entries.iter() {
+            let entry_path = entry.path().to_lowercase();
+
+            if !filters.is_empty() && !filters.iter().any(|f| entry_path.eq(f) || entry_path.starts_with(&format!("{f}/"))) {
+                continue;
+            }
+
+            ark.extract_entry(entry, output_path.join(entry.output_path()))?;
+
+            file_count += 1;
+            total_size += entry.size as u64;
+        }
+
+        println!("Extracted {} files ({} bytes) to \"{}\"", file_count, total_size, output_path.display());
+
+        Ok(())
+    }
+}
+
+impl SubApp for ArkPackApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let input_path = Path::new(&self.input_path);
+        let output_path = Path::new(&self.output_path);
+
+        let mut builder = match input_path.is_dir() {
+            true => ArkBuilder::from_dir(input_path)?,
+            false => ArkBuilder::from_ark(&Ark::open(input_path)?),
+        };
+
+        if let Some(replace_path) = self.replace_path.as_ref() {
+            let file_count = builder.add_dir(replace_path)?;
+            println!("Added {} files from \"{}\"", file_count, replace_path);
+        }
+
+        let ark = builder.write_to_files(output_path)?;
+
+        println!("Wrote {} files in {} parts to \"{}\"", ark.entries.len(), ark.part_sizes.len(), output_path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::apps::SubApp;
+use amp_lib::bank::*;
+use amp_lib::wav::WavData;
+use amp_lib::create_parent_dir;
+use clap::{Parser, Subcommand};
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct BnkApp {
+    #[command(subcommand)]
+    pub commands: BnkCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BnkCommand {
+    #[command(name = "list", about = "List samples in .bnk with offsets and durations from .nse")]
+    List(BnkListApp),
+    #[command(name = "replace", about = "Replace sample in .bnk/.nse with .wav, keeping everything else")]
+    Replace(BnkReplaceApp),
+}
+
+#[derive(Parser, Debug)]
+pub struct BnkListApp {
+    #[arg(help = "Path to amplitude sample bank (.bnk)", required = true)]
+    pub bank_path: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct BnkReplaceApp {
+    #[arg(help = "Path to amplitude sample bank (.bnk)", required = true)]
+    pub bank_path: String,
+    #[arg(help = "Index or name of sample to replace", required = true)]
+    pub sample: String,
+    #[arg(help = "Path to replacement sample (.wav)", required = true)]
+    pub wav_path: String,
+    #[arg(short, long, help = "Path to output sample bank (.bnk), overwrites input if not set")]
+    pub output_path: Option<String>,
+}
+
+impl SubApp for BnkApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.commands {
+            BnkCommand::List(app) => app.process(),
+            BnkCommand::Replace(app) => app.process(),
+        }
+    }
+}
+
+impl SubApp for BnkListApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let bank_path = Path::new(&self.bank_path);
+        let sample_file_path = bank_path.with_extension("nse");
+
+        let mut bnk = BankFile::from_file(bank_path)?;
+        let mut sample_file = std::fs::File::open(&sample_file_path)?;
+        bnk.measure_samples(&mut sample_file)?;
+
+        println!("{:>4}  {:<24} {:>3} {:>6} {:>10} {:>10} {:>8} {:>9}", "#", "Name", "Ch.", "Rate", "Offset", "Size", "Frames", "Duration");
+
+        for (i, sample) in bnk.samples.iter().enumerate() {
+            println!(
+                "{:>4}  {:<24} {:>3} {:>6} {:>#10x} {:>10} {:>8} {:>8.3}s",
+                i,
+                sample.name,
+                sample.channels,
+                sample.sample_rate,
+                sample.pos,
+                sample.data_size().unwrap_or_default(),
+                sample.frame_count.unwrap_or_default(),
+                sample.duration_secs().unwrap_or_default(),
+            );
+        }
+
+        let total_secs = bnk.samples
+            .iter()
+            .flat_map(|s| s.duration_secs())
+            .sum::<f64>();
+
+        println!("{} samples, {:.3}s total", bnk.samples.len(), total_secs);
+
+        Ok(())
+    }
+}
+
+impl SubApp for BnkReplaceApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let bank_path = Path::new(&self.bank_path);
+        let sample_file_path = bank_path.with_extension("nse");
+
+        let output_path = Path::new(self.output_path.as_ref().unwrap_or(&self.bank_path));
+        let output_sample_file_path = output_path.with_extension("nse");
+
+        let mut bnk = BankFile::from_file(bank_path)?;
+        let mut sample_data = std::fs::read(&sample_file_path)?;
+
+        // Find by index first, then by name
+        let index = self.sample
+            .parse::<usize>()
+            .ok()
+            .filter(|i| *i < bnk.samples.len())
+            .or_else(|| bnk.samples.iter().position(|s| s.name.eq(&self.sample)))
+            .ok_or_else(|| format!("Sample \"{}\" not found in bank", self.sample))?;
+
+        let wav = WavData::from_file(&self.wav_path)?;
+        bnk.replace_sample(&mut sample_data, index, &wav.samples, wav.sample_rate, wav.channels, wav.loop_points)?;
+
+        create_parent_dir(output_path)?;
+
+        bnk.write_to_file(output_path)?;
+        std::fs::write(&output_sample_file_path, sample_data)?;
+
+        println!("Replaced sample {} \"{}\" in \"{}\"", index, bnk.samples[index].name, output_path.display());
+
+        Ok(())
+    }
+}
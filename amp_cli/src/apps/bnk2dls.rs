@@ -0,0 +1,43 @@
+use crate::apps::SubApp;
+use amp_lib::bank::*;
+use amp_lib::export::dls::*;
+use amp_lib::create_parent_dir;
+use clap::Parser;
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Bnk2DlsApp {
+    #[arg(help = "Path to input amplitude sample bank (.bnk)", required = true)]
+    pub input_path: String,
+    #[arg(help = "Path to output downloadable sounds collection (.dls)", required = true)]
+    pub output_path: String,
+    #[arg(long, help = "Write DLS level 2 articulation instead of level 1, needed to keep zone pan")]
+    pub level2: bool,
+}
+
+impl SubApp for Bnk2DlsApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let bank_path = Path::new(&self.input_path);
+        let output_path = Path::new(&self.output_path);
+
+        let sample_file_path = bank_path.with_extension("nse");
+        let bank_name = bank_path.file_stem().and_then(|n| n.to_str()).unwrap_or("bank");
+
+        let level = match self.level2 {
+            true => DlsLevel::Two,
+            false => DlsLevel::One,
+        };
+
+        create_parent_dir(output_path)?;
+
+        let bnk = BankFile::from_file(bank_path)?;
+        let mut sample_file = std::fs::File::open(&sample_file_path)?;
+
+        write_dls_to_file(&bnk, &mut sample_file, bank_name, level, output_path)?;
+
+        println!("Wrote {} instruments with {} samples to \"{}\"", bnk.insts.len(), bnk.samples.len(), output_path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use crate::apps::SubApp;
+use amp_lib::bank::*;
+use amp_lib::export::sf2::*;
+use amp_lib::create_parent_dir;
+use clap::Parser;
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Bnk2Sf2App {
+    #[arg(help = "Path to input amplitude sample bank (.bnk)", required = true)]
+    pub input_path: String,
+    #[arg(help = "Path to output soundfont (.sf2)", required = true)]
+    pub output_path: String,
+}
+
+impl SubApp for Bnk2Sf2App {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let bank_path = Path::new(&self.input_path);
+        let output_path = Path::new(&self.output_path);
+
+        let sample_file_path = bank_path.with_extension("nse");
+        let bank_name = bank_path.file_stem().and_then(|n| n.to_str()).unwrap_or("bank");
+
+        create_parent_dir(output_path)?;
+
+        let bnk = BankFile::from_file(bank_path)?;
+        let mut sample_file = std::fs::File::open(&sample_file_path)?;
+
+        write_sf2_to_file(&bnk, &mut sample_file, bank_name, output_path)?;
+
+        println!("Wrote {} presets with {} samples to \"{}\"", bnk.insts.len(), bnk.samples.len(), output_path.display());
+
+        Ok(())
+    }
+}
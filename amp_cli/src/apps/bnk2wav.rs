@@ -1,62 +1,180 @@
 use crate::apps::SubApp;
+use amp_lib::Error;
+use amp_lib::ark::*;
 use amp_lib::bank::*;
+use amp_lib::export::sfz::*;
+use amp_lib::naming::*;
 use grim::io::{FileSearchDepth, PathFinder};
 use clap::Parser;
+use rayon::prelude::*;
 use std::fmt::Debug;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 pub struct Bnk2WavApp {
-    #[arg(help = "Path to input amplitude sample bank (.bnk)", required = true)]
+    #[arg(help = "Path to input amplitude sample bank (.bnk), directory of banks, or archive (.hdr/.iso) containing banks", required = true)]
     pub input_path: String,
     #[arg(help = "Path to output directory", required = true)]
     pub output_path: String,
+    #[arg(long, help = "Also write .sfz instruments referencing extracted samples")]
+    pub sfz: bool,
+    #[arg(short, long, help = "Extract using N threads with memory-mapped .nse files (0 uses all cores)")]
+    pub jobs: Option<usize>,
+    #[arg(long, help = "Sample file name template using {index}, {name} and {file_name} fields (e.g. \"{index:03}_{name}\")", default_value = DEFAULT_SAMPLE_NAME_TEMPLATE)]
+    pub name_template: String,
+}
+
+enum BankSource<'a> {
+    File(PathBuf),
+    Archive(&'a Ark, String),
+}
+
+struct ExtractResult {
+    sample_count: usize,
+    sample_file_path: PathBuf,
+    inst_count: Option<usize>,
 }
 
 impl SubApp for Bnk2WavApp {
     fn process(self) -> Result<(), Box<dyn std::error::Error>> {
         let input_path = Path::new(&self.input_path);
         let output_path = Path::new(&self.output_path);
+        let naming = SampleNaming::new(&self.name_template)?;
 
-        let mut total_samples = 0;
-        let mut bank_count = 0;
+        let ark = match is_archive_path(input_path) {
+            true => Some(Ark::open(input_path)?),
+            false => None,
+        };
 
-        if input_path.is_file() {
-            let samples = extract_samples(input_path, output_path)?;
-            total_samples += samples;
+        let banks = if let Some(ark) = ark.as_ref() {
+            // Banks are read from archive in place, output mirrors archive directories
+            ark.entries
+                .iter()
+                .filter(|e| e.name.to_ascii_lowercase().ends_with(".bnk"))
+                .map(|e| (BankSource::Archive(ark, e.path()), output_path.join(e.output_path().with_extension(""))))
+                .collect::<Vec<_>>()
+        } else if input_path.is_file() {
+            vec![(BankSource::File(input_path.to_path_buf()), output_path.to_path_buf())]
         } else {
-            let bnk_paths = input_path.find_files_with_depth(FileSearchDepth::Immediate)?
+            let mut bnk_paths = input_path.find_files_with_depth(FileSearchDepth::Immediate)?
                 .into_iter()
                 .filter(|p| p.file_name()
                     .and_then(|n| n.to_str())
                     .is_some_and(|n| n.ends_with(".bnk"))) // Note: is_some_and is 1.70.0 feature
                 .collect::<Vec<_>>();
 
-            for bnk_path in bnk_paths {
-                let local_output_path = output_path.join(bnk_path.file_stem().unwrap());
+            bnk_paths.sort();
+
+            bnk_paths
+                .into_iter()
+                .map(|p| {
+                    let local_output_path = output_path.join(p.file_stem().unwrap());
+                    (BankSource::File(p), local_output_path)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let results = match self.jobs {
+            Some(jobs) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(jobs)
+                    .build()?;
 
-                let samples = extract_samples(bnk_path.as_path(), local_output_path.as_path())?;
-                total_samples += samples;
-                bank_count += 1;
+                // Banks and their samples are both split across threads, results kept in bank order
+                pool.install(|| banks
+                    .par_iter()
+                    .map(|(source, out_path)| extract_bank_samples(source, out_path, &naming, self.sfz, true))
+                    .collect::<Vec<_>>())
+            },
+            None => banks
+                .iter()
+                .map(|(source, out_path)| extract_bank_samples(source, out_path, &naming, self.sfz, false))
+                .collect(),
+        };
+
+        let mut total_samples = 0;
+
+        for ((_, out_path), result) in banks.iter().zip(results) {
+            let result = result?;
+
+            println!("Wrote {} samples to \"{}\"", result.sample_count, result.sample_file_path.display());
+
+            if let Some(inst_count) = result.inst_count {
+                println!("Wrote {} instruments to \"{}\"", inst_count, out_path.display());
             }
+
+            total_samples += result.sample_count;
         }
 
-        //println!("Wrote {} samples to \"{}\"", bnk.samples.len(), sample_file_path.display());
-        println!("Extracted {} total samples from {} banks", total_samples, bank_count);
+        println!("Extracted {} total samples from {} banks", total_samples, banks.len());
 
         Ok(())
     }
 }
 
-fn extract_samples(bank_path: &Path, output_path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+fn is_archive_path(path: &Path) -> bool {
+    path.is_file() && path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("hdr") || e.eq_ignore_ascii_case("iso"))
+}
+
+fn extract_bank_samples(source: &BankSource, output_path: &Path, naming: &SampleNaming, sfz: bool, parallel: bool) -> Result<ExtractResult, Error> {
+    match source {
+        BankSource::File(bank_path) => extract_samples(bank_path, output_path, naming, sfz, parallel),
+        BankSource::Archive(ark, bank_path) => extract_archive_samples(ark, bank_path, output_path, naming, sfz, parallel),
+    }
+}
+
+fn extract_samples(bank_path: &Path, output_path: &Path, naming: &SampleNaming, sfz: bool, parallel: bool) -> Result<ExtractResult, Error> {
     let sample_file_path = bank_path
         .canonicalize()
         .map(|fp| fp.parent().unwrap().join(format!("{}.nse", bank_path.file_stem().unwrap().to_str().unwrap())))?;
 
-    let bnk = BankFile::from_file(bank_path)?;
-    bnk.extract_samples_to_dir(&sample_file_path, output_path)?;
+    let mut bnk = BankFile::from_file(bank_path)?;
+
+    match parallel {
+        true => bnk.extract_samples_to_dir_parallel(&sample_file_path, output_path, naming)?,
+        false => bnk.extract_samples_to_dir(&sample_file_path, output_path, naming)?,
+    }
+
+    // Loop points were filled in while extracting
+    let inst_count = match sfz {
+        true => Some(write_sfz_to_dir(&bnk, naming, output_path)?),
+        false => None,
+    };
 
-    println!("Wrote {} samples to \"{}\"", bnk.samples.len(), sample_file_path.display());
+    Ok(ExtractResult {
+        sample_count: bnk.samples.len(),
+        sample_file_path,
+        inst_count,
+    })
+}
+
+fn extract_archive_samples(ark: &Ark, bank_path: &str, output_path: &Path, naming: &SampleNaming, sfz: bool, parallel: bool) -> Result<ExtractResult, Error> {
+    let (mut bnk, mut sample_reader) = ark.open_bank(bank_path)?;
 
-    Ok(bnk.samples.len())
-}
\ No newline at end of file
+    match parallel {
+        true => {
+            // Entry is read into memory once so threads don't share a reader
+            let mut sample_data = Vec::with_capacity(sample_reader.len() as usize);
+            sample_reader.read_to_end(&mut sample_data)?;
+
+            bnk.extract_samples_from_slice_parallel(&sample_data, output_path, naming)?
+        },
+        false => bnk.extract_samples_from_reader(&mut sample_reader, output_path, naming)?,
+    }
+
+    // Loop points were filled in while extracting
+    let inst_count = match sfz {
+        true => Some(write_sfz_to_dir(&bnk, naming, output_path)?),
+        false => None,
+    };
+
+    Ok(ExtractResult {
+        sample_count: bnk.samples.len(),
+        sample_file_path: Path::new(bank_path).with_extension("nse"),
+        inst_count,
+    })
+}
@@ -0,0 +1,69 @@
+use crate::apps::SubApp;
+use amp_lib::naming::*;
+use amp_lib::render::*;
+use amp_lib::wav::WavWriter;
+use amp_lib::{create_output_dir, create_parent_dir};
+use clap::Parser;
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Mid2WavApp {
+    #[arg(help = "Path to input amplitude song midi (.mid)", required = true)]
+    pub input_path: String,
+    #[arg(help = "Path to output wav file (or directory if rendering stems)", required = true)]
+    pub output_path: String,
+    #[arg(short, long, help = "Output sample rate", default_value_t = DEFAULT_RENDER_SAMPLE_RATE)]
+    pub sample_rate: u32,
+    #[arg(long, help = "Render each instrument track (with notes) to its own .wav in output directory")]
+    pub stems: bool,
+}
+
+impl SubApp for Mid2WavApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let input_path = Path::new(&self.input_path);
+        let output_path = Path::new(&self.output_path);
+
+        let options = RenderOptions {
+            sample_rate: self.sample_rate,
+            ..Default::default()
+        };
+
+        if self.stems {
+            create_output_dir(output_path)?;
+
+            let stems = render_song_stems(input_path, &options)?;
+            let mut unique_names = UniqueNames::new();
+
+            for (i, stem) in stems.iter().enumerate() {
+                let file_name = match sanitize_file_name(&stem.name) {
+                    name if name.is_empty() => format!("stem_{i}"),
+                    name => name,
+                };
+
+                let file_name = unique_names.insert(file_name);
+
+                let stem_path = output_path.join(format!("{file_name}.wav"));
+
+                let wav = WavWriter::new(stem.pcm.as_slice(), 2, options.sample_rate);
+                wav.encode_to_file(&stem_path)?;
+
+                println!("Wrote \"{}\" stem to \"{}\"", stem.name, stem_path.display());
+            }
+
+            println!("Rendered {} stems", stems.len());
+            return Ok(());
+        }
+
+        create_parent_dir(output_path)?;
+
+        let pcm = render_song(input_path, &options)?;
+
+        let wav = WavWriter::new(pcm.as_slice(), 2, options.sample_rate);
+        wav.encode_to_file(output_path)?;
+
+        println!("Wrote {:.2}s of audio to \"{}\"", (pcm.len() / 2) as f64 / options.sample_rate as f64, output_path.display());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,18 @@
+mod ark;
+mod bnk;
+mod bnk2dls;
+mod bnk2sf2;
 mod bnk2wav;
+mod mid2wav;
+mod wav2bnk;
 
+use ark::*;
+use bnk::*;
+use bnk2dls::*;
+use bnk2sf2::*;
 use bnk2wav::*;
+use mid2wav::*;
+use wav2bnk::*;
 use clap::{Parser, Subcommand};
 
 // From Cargo.toml
@@ -20,8 +32,20 @@ struct Options {
 
 #[derive(Subcommand, Debug)]
 enum SubCommand {
+    #[command(name = "ark", about = "List or extract files in .hdr/.ark archive")]
+    Ark(ArkApp),
+    #[command(name = "bnk", about = "List or edit samples in existing .bnk")]
+    Bnk(BnkApp),
+    #[command(name = "bnk2dls", about = "Convert .bnk instruments to downloadable sounds (.dls)")]
+    Bnk2Dls(Bnk2DlsApp),
+    #[command(name = "bnk2sf2", about = "Convert .bnk instruments to soundfont (.sf2)")]
+    Bnk2Sf2(Bnk2Sf2App),
     #[command(name = "bnk2wav", about = "Extract audio samples from .bnk")]
     Bnk2Wav(Bnk2WavApp),
+    #[command(name = "mid2wav", about = "Render song midi to .wav using its sample banks")]
+    Mid2Wav(Mid2WavApp),
+    #[command(name = "wav2bnk", about = "Build .bnk and .nse from .wav samples and manifest")]
+    Wav2Bnk(Wav2BnkApp),
 }
 
 #[derive(Debug)]
@@ -38,7 +62,13 @@ impl AmpTool {
 
     pub fn run(self) -> Result<(), Box<dyn std::error::Error>> {
         match self.options.commands {
+            SubCommand::Ark(app) => app.process(),
+            SubCommand::Bnk(app) => app.process(),
+            SubCommand::Bnk2Dls(app) => app.process(),
+            SubCommand::Bnk2Sf2(app) => app.process(),
             SubCommand::Bnk2Wav(app) => app.process(),
+            SubCommand::Mid2Wav(app) => app.process(),
+            SubCommand::Wav2Bnk(app) => app.process(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,34 @@
+use crate::apps::SubApp;
+use amp_lib::builder::*;
+use amp_lib::create_parent_dir;
+use clap::Parser;
+use std::fmt::Debug;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Wav2BnkApp {
+    #[arg(help = "Path to directory of input samples (.wav)", required = true)]
+    pub input_path: String,
+    #[arg(help = "Path to manifest describing banks, instruments and zones (.toml or .json)", required = true)]
+    pub manifest_path: String,
+    #[arg(help = "Path to output sample bank (.bnk), sample data (.nse) is written alongside", required = true)]
+    pub output_path: String,
+}
+
+impl SubApp for Wav2BnkApp {
+    fn process(self) -> Result<(), Box<dyn std::error::Error>> {
+        let input_dir = Path::new(&self.input_path);
+        let bank_path = Path::new(&self.output_path);
+        let sample_file_path = bank_path.with_extension("nse");
+
+        create_parent_dir(bank_path)?;
+
+        let manifest = BankManifest::from_file(&self.manifest_path)?;
+        let bnk = BankBuilder::from_manifest(&manifest, input_dir)?
+            .write_to_files(bank_path, &sample_file_path)?;
+
+        println!("Wrote {} samples, {} instruments and {} zones to \"{}\"", bnk.samples.len(), bnk.insts.len(), bnk.sdes.len(), bank_path.display());
+
+        Ok(())
+    }
+}
@@ -1,24 +1,64 @@
+use amp_lib::ark::*;
 use amp_lib::bank::*;
+use amp_lib::song::*;
+use amp_lib::timeline::*;
 use eframe::{egui::{self, Align, Align2, Color32, FontId, Pos2, RichText, Visuals, Widget, TextBuffer}, glow};
 use grim::io::{FileSearchDepth, PathFinder};
-use grim::midi::{MidiEvent, MidiFile, MidiText, MidiTextType};
 use std::path::{Path, PathBuf};
 use super::VERSION;
 
 #[derive(Default)]
 pub struct AmpApp {
     dir_path: Option<PathBuf>,
-    bank_file: Option<BankFile>,
+    song: Option<AmpSong>,
+    timeline: Option<BankTimeline>,
+    archive_banks: Vec<(String, BankFile)>, // Banks read from .hdr/.iso when no song is open
+    selected_bank_index: usize,
     selected_sample_index: usize,
 }
 
 impl AmpApp {
     fn reset_state(&mut self) {
         self.dir_path = None;
-        self.bank_file = None;
+        self.song = None;
+        self.timeline = None;
+        self.archive_banks.clear();
+        self.selected_bank_index = 0;
         self.selected_sample_index = 0;
     }
 
+    /// Opens every bank in archive (.hdr) or disc image (.iso) without extracting
+    pub fn open_archive(&mut self, ark_path: PathBuf) {
+        self.reset_state();
+
+        let ark = match Ark::open(&ark_path) {
+            Ok(ark) => ark,
+            Err(e) => {
+                println!("Failed to open \"{}\": {e}", ark_path.display());
+                return;
+            }
+        };
+
+        println!("Found {} files!", ark.entries.len());
+
+        for entry in ark.entries.iter().filter(|e| e.name.to_ascii_lowercase().ends_with(".bnk")) {
+            let bank_path = entry.path();
+
+            let bank = ark.open_bank(&bank_path)
+                .and_then(|(mut bank, mut sample_reader)| {
+                    bank.measure_samples(&mut sample_reader)?;
+                    Ok(bank)
+                });
+
+            match bank {
+                Ok(bank) => self.archive_banks.push((bank_path, bank)),
+                Err(e) => println!("Skipping \"{bank_path}\": {e}"),
+            }
+        }
+
+        println!("Found {} banks", self.archive_banks.len());
+    }
+
     pub fn open_directory(&mut self, dir_path: PathBuf) {
         self.reset_state();
 
@@ -35,46 +75,36 @@ impl AmpApp {
         println!("Found {} midi files!", mid_file_paths.len());
 
         for mp in mid_file_paths.iter() {
-            let mf = MidiFile::from_path(mp).unwrap();
-
-            let bank_track = mf.tracks
-                .iter()
-                .find(|t| t.name
-                    .as_ref()
-                    .is_some_and(|n| n.as_str().eq("BANK")))
-                .unwrap();
-
-            let bank_events = bank_track
-                .events
-                .iter()
-                .flat_map(|e| match e {
-                    MidiEvent::Meta(mt @ MidiText { pos_realtime: Some(pos), .. }) if mt.is_text()
-                        => Some((*pos, mt.as_str().unwrap())),
-                    _ => None
-                })
-                .collect::<Vec<_>>();
-
-            println!("Found {} bank events", bank_events.len());
-
-            /*for (pos, name) in bank_events.iter() {
-                println!("{pos}ms: {name}");
-            }*/
-
-            // Open bank file
-            if let Some((_, name)) = bank_events.first() {
-                println!("Opening {name}");
-
-                let bank_path = dir_path.join(name);
-                let bank_file = BankFile::from_file(&bank_path).unwrap();
-
-                println!("Found {} samples", bank_file.samples.len());
-                print!("{bank_file:#?}");
-
-                // Update bank file
-                self.dir_path = Some(dir_path);
-                self.bank_file = Some(bank_file);
+            let song = match AmpSong::from_file(mp) {
+                Ok(song) => song,
+                Err(e) => {
+                    println!("Skipping \"{}\": {e}", mp.display());
+                    continue;
+                }
+            };
+
+            println!("Found {} bank events", song.bank_switches.len());
+            println!("Found {} instrument lanes", song.lanes.len());
+
+            // Open every bank file used by song
+            let timeline = match BankTimeline::from_song(&song, mp) {
+                Ok(timeline) => timeline,
+                Err(e) => {
+                    println!("Failed to open banks of \"{}\": {e}", mp.display());
+                    continue;
+                }
+            };
+
+            for segment in timeline.segments.iter() {
+                let bank = &timeline.banks[segment.bank_index];
+                println!("{:.3}s: {} ({} samples)", segment.start_secs, bank.name, bank.bank.samples.len());
             }
 
+            // Update bank timeline
+            self.dir_path = Some(dir_path);
+            self.song = Some(song);
+            self.timeline = Some(timeline);
+
             break;
         }
     }
@@ -86,6 +116,41 @@ impl eframe::App for AmpApp {
 
         egui::CentralPanel::default().show(ctx, |ui| {
             //ui.with_layout(egui::Layout::left_to_right(Align::Center), |ui| {
+                if let Some(song) = self.song.as_ref() {
+                    ui.label(format!("{} lanes, {} bank switches, {} sections, {:.1}s",
+                        song.lanes.len(),
+                        song.bank_switches.len(),
+                        song.sections.len(),
+                        song.length_secs()));
+                }
+
+                // Bank used by each segment of song
+                if let Some(timeline) = self.timeline.as_ref() {
+                    ui.horizontal(|ui| {
+                        for segment in timeline.segments.iter() {
+                            let name = &timeline.banks[segment.bank_index].name;
+                            let selected = segment.bank_index == self.selected_bank_index;
+
+                            if ui.selectable_label(selected, format!("{:.1}s: {name}", segment.start_secs)).clicked() {
+                                self.selected_bank_index = segment.bank_index;
+                                self.selected_sample_index = 0;
+                            }
+                        }
+                    });
+                }
+
+                // Every bank in opened archive
+                if self.timeline.is_none() {
+                    ui.horizontal_wrapped(|ui| {
+                        for (i, (bank_path, _)) in self.archive_banks.iter().enumerate() {
+                            if ui.selectable_label(i == self.selected_bank_index, bank_path.as_str()).clicked() {
+                                self.selected_bank_index = i;
+                                self.selected_sample_index = 0;
+                            }
+                        }
+                    });
+                }
+
                 let table = TableBuilder::new(ui)
                     .striped(true)
                     .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
@@ -93,16 +158,27 @@ impl eframe::App for AmpApp {
                     .column(Column::auto())
                     .column(Column::auto())
                     .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::auto())
+                    .column(Column::auto())
                     //.column(Column::auto())
                     .column(Column::remainder())
                     .header(20., |mut header| {
                         header.col(|ui| { ui.strong("#"); });
                         header.col(|ui| { ui.strong("Name"); });
                         header.col(|ui| { ui.strong("Ch."); });
+                        header.col(|ui| { ui.strong("Rate"); });
+                        header.col(|ui| { ui.strong("Length"); });
+                        header.col(|ui| { ui.strong("Size"); });
                         header.col(|ui| { ui.strong("Source"); });
                 });
 
-                let Some(bank) = self.bank_file.as_ref() else {
+                let bank = match self.timeline.as_ref() {
+                    Some(timeline) => timeline.banks.get(self.selected_bank_index).map(|b| &b.bank),
+                    None => self.archive_banks.get(self.selected_bank_index).map(|(_, b)| b),
+                };
+
+                let Some(bank) = bank else {
                     return
                 };
 
@@ -112,6 +188,9 @@ impl eframe::App for AmpApp {
                             row.col(|ui| { ui.label(i.to_string()); });
                             row.col(|ui| { ui.label(sample.name.as_str()); });
                             row.col(|ui| { ui.label(sample.channels.to_string()); });
+                            row.col(|ui| { ui.label(sample.sample_rate.to_string()); });
+                            row.col(|ui| { ui.label(sample.duration_secs().map(|d| format!("{d:.3}s")).unwrap_or_default()); });
+                            row.col(|ui| { ui.label(sample.data_size().map(|s| s.to_string()).unwrap_or_default()); });
                             row.col(|ui| { ui.label(sample.file_name.as_str()); });
                         });
                     }
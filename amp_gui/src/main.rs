@@ -24,10 +24,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
-    let dir_path = Path::new(&args[0]);
+    let input_path = Path::new(&args[0]);
     let mut app = AmpApp::default();
 
-    app.open_directory(dir_path.into());
+    // Archives and disc images are read in place, otherwise expects song directory
+    let is_archive = input_path.is_file() && input_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("hdr") || e.eq_ignore_ascii_case("iso"));
+
+    match is_archive {
+        true => app.open_archive(input_path.into()),
+        false => app.open_directory(input_path.into()),
+    }
 
     let ops = NativeOptions {
         drag_and_drop_support: true,
@@ -0,0 +1,878 @@
+use crate::bank::*;
+use crate::iso::*;
+use crate::naming::sanitize_file_name;
+use crate::{create_parent_dir, Error, SimpleReader, SimpleWriter, SubStreamReader};
+use grim::io::{FileSearchDepth, PathFinder};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Entry stream read from file on disk
+pub type ArkFileReader = SubStreamReader<BufReader<File>>;
+
+/// File in archive, offset is across all .ark parts
+#[derive(Clone, Debug, Default)]
+pub struct ArkEntry {
+    pub name: String,
+    pub dir: String,
+    pub offset: u64,
+    pub size: u32,
+    pub inflated_size: u32, // Non-zero if compressed, data is read as-is
+    pub part: usize,
+    pub part_offset: u64,
+    pub(crate) name_index: u32,
+    pub(crate) dir_index: u32,
+}
+
+impl ArkEntry {
+    /// Full path in archive (i.e. "songs/tut0/tut0.mid")
+    pub fn path(&self) -> String {
+        match self.dir.is_empty() {
+            true => self.name.to_owned(),
+            false => format!("{}/{}", self.dir, self.name),
+        }
+    }
+
+    /// Relative path to extract to, each part of path made safe
+    pub fn output_path(&self) -> PathBuf {
+        self.path()
+            .split(['/', '\\'])
+            .map(sanitize_file_name)
+            .filter(|p| !p.is_empty())
+            .collect()
+    }
+}
+
+/// Archive file table from .hdr, with data split across .ark parts
+///
+/// Header starts with version, size of each part, then string table (null terminated names and offsets to them)
+/// and entries. Entries reference file and dir names by string index. Only version 2 is supported.
+#[derive(Debug, Default)]
+pub struct Ark {
+    pub version: u32,
+    pub part_sizes: Vec<u64>,
+    pub part_paths: Vec<PathBuf>,
+    pub part_offsets: Vec<u64>, // Start of each part in file, non-zero if part is in disc image
+    pub entries: Vec<ArkEntry>,
+    pub(crate) strings: ArkStrings, // Kept as-is so unmodified archive is rebuilt exactly
+}
+
+impl Ark {
+    /// Opens archive from .hdr, or from first .hdr found in disc image (.iso)
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let is_iso = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("iso"));
+
+        if !is_iso {
+            return Self::from_path(path);
+        }
+
+        let iso = IsoImage::from_path(path)?;
+
+        let hdr_path = iso.find_ark_headers()
+            .first()
+            .map(|e| e.path.to_owned())
+            .ok_or_else(|| Error::MissingIsoEntry {
+                path: String::from("*.hdr"),
+            })?;
+
+        Self::from_iso(&iso, &hdr_path)
+    }
+
+    /// Reads .hdr from disc image, parts are read in place from image
+    pub fn from_iso(iso: &IsoImage, hdr_path: &str) -> Result<Self, Error> {
+        let hdr_entry = iso.find_entry(hdr_path)
+            .ok_or_else(|| Error::MissingIsoEntry {
+                path: hdr_path.to_owned(),
+            })?;
+
+        let mut ark = Self::from_reader(&mut iso.open_entry(hdr_entry)?)?;
+
+        let part_entries = (0..ark.part_sizes.len())
+            .map(|i| {
+                let part_path = ark_part_path(&hdr_entry.path, i).to_string_lossy().to_string();
+
+                iso.find_entry(&part_path)
+                    .ok_or(Error::MissingIsoEntry {
+                        path: part_path,
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        ark.part_paths = vec![iso.path.to_owned(); part_entries.len()];
+        ark.part_offsets = part_entries
+            .iter()
+            .map(|e| e.offset)
+            .collect();
+
+        Ok(ark)
+    }
+
+    /// Reads .hdr, parts are found next to it (i.e. "MAIN.HDR" has "MAIN_0.ARK", "MAIN_1.ARK" ...)
+    pub fn from_path<T: AsRef<Path>>(hdr_path: T) -> Result<Self, Error> {
+        let hdr_path = hdr_path.as_ref();
+
+        let mut file = BufReader::new(File::open(hdr_path)?);
+        let mut ark = Self::from_reader(&mut file)?;
+
+        ark.part_paths = (0..ark.part_sizes.len())
+            .map(|i| ark_part_path(hdr_path, i))
+            .collect();
+        ark.part_offsets = vec![0; ark.part_sizes.len()];
+
+        Ok(ark)
+    }
+
+    /// Reads file table from .hdr, part paths are left empty
+    pub fn from_reader<T: Read + Seek>(reader: &mut T) -> Result<Self, Error> {
+        let version = reader.read_u32()?;
+
+        if version != ARK_VERSION {
+            return Err(Error::UnsupportedArkVersion {
+                version,
+            });
+        }
+
+        let part_count = reader.read_u32()?;
+        let part_sizes = (0..part_count)
+            .map(|_| reader.read_u32().map(|s| s as u64))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let string_data = reader.read_string_bytes()?;
+
+        let string_count = reader.read_u32()?;
+        let string_offsets = (0..string_count)
+            .map(|_| reader.read_u32())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let strings = ArkStrings {
+            data: string_data,
+            offsets: string_offsets,
+        };
+
+        let entry_count = reader.read_u32()?;
+
+        // Don't reserve more entries than rest of header can hold
+        let max_entry_count = reader.remaining_len()? / ARK_ENTRY_SIZE;
+        let mut entries = Vec::with_capacity((entry_count as u64).min(max_entry_count) as usize);
+
+        for index in 0..(entry_count as usize) {
+            let offset = reader.read_u32()? as u64;
+
+            let name_index = reader.read_u32()?;
+            let dir_index = reader.read_u32()?;
+            let size = reader.read_u32()?;
+            let inflated_size = reader.read_u32()?;
+
+            let name = strings
+                .get(name_index)
+                .ok_or_else(|| Error::InvalidArkEntry { index, message: format!("File name index {name_index} out of range") })?;
+
+            // Files in root use invalid dir index
+            let dir = strings
+                .get(dir_index)
+                .unwrap_or_default();
+
+            let (part, part_offset) = find_part(&part_sizes, offset, size as u64)
+                .ok_or_else(|| Error::InvalidArkEntry { index, message: format!("Data at offset {offset} (size {size}) is outside of ark parts") })?;
+
+            entries.push(ArkEntry {
+                name,
+                dir,
+                offset,
+                size,
+                inflated_size,
+                part,
+                part_offset,
+                name_index,
+                dir_index,
+            });
+        }
+
+        Ok(Self {
+            version,
+            part_sizes,
+            part_paths: Vec::new(),
+            part_offsets: Vec::new(),
+            entries,
+            strings,
+        })
+    }
+
+    /// Finds entry by path, ignoring case and slash direction
+    pub fn find_entry(&self, path: &str) -> Option<&ArkEntry> {
+        let path = path.replace('\\', "/");
+        let path = path.trim_start_matches('/');
+
+        self.entries
+            .iter()
+            .find(|e| e.path().eq_ignore_ascii_case(path))
+    }
+
+    /// Opens entry data as its own stream
+    pub fn open_entry(&self, entry: &ArkEntry) -> Result<ArkFileReader, Error> {
+        let Some(part_path) = self.part_paths.get(entry.part) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Path of ark part {} is unknown", entry.part)).into());
+        };
+
+        let part_start = self.part_offsets.get(entry.part).copied().unwrap_or_default();
+
+        Ok(SubStreamReader::new(BufReader::new(File::open(part_path)?), part_start + entry.part_offset, entry.size as u64)?)
+    }
+
+    /// Reads .bnk from archive and opens its .nse for streaming samples
+    pub fn open_bank(&self, bank_path: &str) -> Result<(BankFile, ArkFileReader), Error> {
+        let bank_entry = self.find_entry(bank_path)
+            .ok_or_else(|| Error::MissingArkEntry {
+                path: bank_path.to_owned(),
+            })?;
+
+        let sample_path = Path::new(&bank_entry.path())
+            .with_extension("nse")
+            .to_string_lossy()
+            .to_string();
+
+        let sample_entry = self.find_entry(&sample_path)
+            .ok_or(Error::MissingArkEntry {
+                path: sample_path,
+            })?;
+
+        let bank = BankFile::from_reader(&mut self.open_entry(bank_entry)?)?;
+        let sample_reader = self.open_entry(sample_entry)?;
+
+        Ok((bank, sample_reader))
+    }
+
+    /// Copies entry data to file, creating parent directories
+    pub fn extract_entry<T: AsRef<Path>>(&self, entry: &ArkEntry, output_path: T) -> Result<(), Error> {
+        let output_path = output_path.as_ref();
+
+        create_parent_dir(output_path)?;
+
+        let mut reader = self.open_entry(entry)?;
+        let mut writer = std::io::BufWriter::new(File::create(output_path)?);
+
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+pub const ARK_VERSION: u32 = 2;
+
+/// Data of entries starts on DVD sector
+pub const ARK_BLOCK_SIZE: u64 = 0x800;
+const ARK_ENTRY_SIZE: u64 = 20;
+pub const DEFAULT_MAX_ARK_PART_SIZE: u64 = 0x4000_0000;
+
+#[derive(Debug)]
+enum ArkSource {
+    File(PathBuf),
+    Part {
+        path: PathBuf,
+        offset: u64,
+    },
+}
+
+/// Part of source archive, read for padding between entries
+#[derive(Debug)]
+struct ArkSourcePart {
+    path: PathBuf,
+    offset: u64, // Start of part in file
+}
+
+#[derive(Debug)]
+struct ArkBuilderEntry {
+    name: String,
+    dir: String,
+    name_index: u32,
+    dir_index: u32,
+    size: u64,
+    inflated_size: u32,
+    source: ArkSource,
+    original: Option<(usize, u64, u64)>, // Part, offset in part and size in source archive
+}
+
+impl ArkBuilderEntry {
+    fn path(&self) -> String {
+        match self.dir.is_empty() {
+            true => self.name.to_owned(),
+            false => format!("{}/{}", self.dir, self.name),
+        }
+    }
+}
+
+/// Builds .hdr and .ark parts from files on disk and/or existing archive
+///
+/// Entries from existing archive keep their order, string indices and place in part while they still fit.
+/// Padding between entries is copied from parts where nothing moved, so archive without changes is rebuilt
+/// byte for byte. Anything moved or added starts on next block, with zero padding in its part.
+#[derive(Debug)]
+pub struct ArkBuilder {
+    max_part_size: u64,
+    part_sizes: Vec<u64>,
+    source_parts: Vec<ArkSourcePart>,
+    strings: ArkStrings,
+    entries: Vec<ArkBuilderEntry>,
+}
+
+impl Default for ArkBuilder {
+    fn default() -> Self {
+        Self {
+            max_part_size: DEFAULT_MAX_ARK_PART_SIZE,
+            part_sizes: Vec::new(),
+            source_parts: Vec::new(),
+            strings: ArkStrings::default(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl ArkBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from existing archive, entries are copied from its parts when written
+    pub fn from_ark(ark: &Ark) -> Self {
+        let entries = ark.entries
+            .iter()
+            .map(|e| ArkBuilderEntry {
+                name: e.name.to_owned(),
+                dir: e.dir.to_owned(),
+                name_index: e.name_index,
+                dir_index: e.dir_index,
+                size: e.size as u64,
+                inflated_size: e.inflated_size,
+                source: ArkSource::Part {
+                    path: ark.part_paths.get(e.part).cloned().unwrap_or_default(),
+                    offset: ark.part_offsets.get(e.part).copied().unwrap_or_default() + e.part_offset,
+                },
+                original: Some((e.part, e.part_offset, e.size as u64)),
+            })
+            .collect();
+
+        let source_parts = ark.part_paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| ArkSourcePart {
+                path: path.to_owned(),
+                offset: ark.part_offsets.get(i).copied().unwrap_or_default(),
+            })
+            .collect();
+
+        Self {
+            part_sizes: ark.part_sizes.to_owned(),
+            source_parts,
+            strings: ark.strings.to_owned(),
+            entries,
+            ..Default::default()
+        }
+    }
+
+    /// Adds every file in directory tree, paths in archive are relative to directory
+    pub fn from_dir<T: AsRef<Path>>(dir_path: T) -> Result<Self, Error> {
+        let mut builder = Self::new();
+        builder.add_dir(dir_path)?;
+
+        Ok(builder)
+    }
+
+    /// Size new entries can fill part up to before starting next part
+    pub fn with_max_part_size(mut self, max_part_size: u64) -> Self {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    /// Adds or replaces files from directory tree in path order, returns number of files
+    pub fn add_dir<T: AsRef<Path>>(&mut self, dir_path: T) -> Result<usize, Error> {
+        let dir_path = dir_path.as_ref();
+
+        let mut file_paths = dir_path.find_files_with_depth(FileSearchDepth::Recursive)?;
+        file_paths.sort();
+
+        for file_path in file_paths.iter() {
+            let ark_path = file_path
+                .strip_prefix(dir_path)
+                .unwrap_or(file_path)
+                .components()
+                .filter_map(|c| c.as_os_str().to_str())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            self.add_file(&ark_path, file_path)?;
+        }
+
+        Ok(file_paths.len())
+    }
+
+    /// Adds file at path in archive (i.e. "songs/tut0/tut0.mid"), replacing entry with same path
+    pub fn add_file<T: AsRef<Path>>(&mut self, ark_path: &str, file_path: T) -> Result<(), Error> {
+        let file_path = file_path.as_ref();
+        let size = std::fs::metadata(file_path)?.len();
+
+        let ark_path = ark_path.replace('\\', "/");
+        let ark_path = ark_path.trim_matches('/');
+
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.path().eq_ignore_ascii_case(ark_path)) {
+            entry.size = size;
+            entry.inflated_size = 0;
+            entry.source = ArkSource::File(file_path.to_path_buf());
+
+            return Ok(());
+        }
+
+        let (dir, name) = ark_path
+            .rsplit_once('/')
+            .unwrap_or(("", ark_path));
+
+        let name_index = self.strings.find_or_add(name);
+
+        // Files in root use invalid dir index
+        let dir_index = match dir.is_empty() {
+            true => u32::MAX,
+            false => self.strings.find_or_add(dir),
+        };
+
+        self.entries.push(ArkBuilderEntry {
+            name: name.to_owned(),
+            dir: dir.to_owned(),
+            name_index,
+            dir_index,
+            size,
+            inflated_size: 0,
+            source: ArkSource::File(file_path.to_path_buf()),
+            original: None,
+        });
+
+        Ok(())
+    }
+
+    /// Writes .hdr and .ark parts next to it, returns written archive
+    pub fn write_to_files<T: AsRef<Path>>(&self, hdr_path: T) -> Result<Ark, Error> {
+        let hdr_path = hdr_path.as_ref();
+
+        let (positions, part_sizes, part_changed) = self.layout();
+        let header = self.write_header(&positions, &part_sizes)?;
+
+        let part_paths = (0..part_sizes.len())
+            .map(|i| ark_part_path(hdr_path, i))
+            .collect::<Vec<_>>();
+
+        // Source parts are read while writing
+        let overwrites_source = self.entries
+            .iter()
+            .filter_map(|e| match &e.source {
+                ArkSource::Part { path, .. } => Some(path),
+                _ => None,
+            })
+            .chain(self.source_parts.iter().map(|p| &p.path))
+            .any(|path| part_paths.iter().any(|p| is_same_file(p, path)));
+
+        if overwrites_source {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Can't write over .ark parts of archive being rebuilt").into());
+        }
+
+        create_parent_dir(hdr_path)?;
+
+        for (part, (part_path, part_size)) in part_paths.iter().zip(part_sizes.iter()).enumerate() {
+            let mut part_entries = positions
+                .iter()
+                .enumerate()
+                .filter(|(_, (p, _))| *p == part)
+                .map(|(i, (_, offset))| (*offset, i))
+                .collect::<Vec<_>>();
+
+            part_entries.sort();
+
+            // Entries of unchanged part are all in original place, so gaps match source part
+            let mut gap_source = match self.source_parts.get(part).filter(|_| !part_changed[part]) {
+                Some(source_part) => Some((File::open(&source_part.path)?, source_part.offset)),
+                None => None,
+            };
+
+            let mut writer = std::io::BufWriter::new(File::create(part_path)?);
+            let mut pos = 0;
+
+            for (offset, i) in part_entries {
+                let entry = &self.entries[i];
+                write_gap(&mut writer, gap_source.as_mut(), pos, offset - pos)?;
+
+                let mut reader = match &entry.source {
+                    ArkSource::File(path) => File::open(path)?,
+                    ArkSource::Part { path, offset } => {
+                        let mut file = File::open(path)?;
+                        file.seek(SeekFrom::Start(*offset))?;
+                        file
+                    }
+                };
+
+                let copied = std::io::copy(&mut (&mut reader).take(entry.size), &mut writer)?;
+
+                if copied != entry.size {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("Source of \"{}\" is shorter than expected", entry.path())).into());
+                }
+
+                pos = offset + entry.size;
+            }
+
+            write_gap(&mut writer, gap_source.as_mut(), pos, part_size - pos)?;
+            writer.flush()?;
+        }
+
+        std::fs::write(hdr_path, header)?;
+
+        Ark::from_path(hdr_path)
+    }
+
+    /// Places entries in parts, returns part and offset in part of each entry with size of each part
+    /// and whether anything in part moved or changed size
+    fn layout(&self) -> (Vec<(usize, u64)>, Vec<u64>, Vec<bool>) {
+        let mut part_ends = vec![0u64; self.part_sizes.len().max(1)];
+        let mut part_changed = vec![false; part_ends.len()];
+        let mut positions = vec![(0, 0); self.entries.len()];
+
+        // Entries from source archive in data order, then new entries
+        let mut order = (0..self.entries.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| match self.entries[*i].original {
+            Some((part, offset, _)) => (0, part, offset),
+            None => (1, 0, 0),
+        });
+
+        for i in order {
+            let entry = &self.entries[i];
+
+            let (part, offset) = match entry.original {
+                Some((part, offset, size)) if offset >= part_ends[part] => {
+                    part_changed[part] |= size != entry.size;
+                    (part, offset)
+                },
+                Some((part, _, _)) => {
+                    // Previous entry grew into this one
+                    part_changed[part] = true;
+                    (part, align_to_block(part_ends[part]))
+                },
+                None => {
+                    let mut part = part_ends.len() - 1;
+                    let mut offset = align_to_block(part_ends[part]);
+
+                    if part_ends[part] > 0 && (offset + entry.size) > self.max_part_size {
+                        part_ends.push(0);
+                        part_changed.push(true);
+
+                        part += 1;
+                        offset = 0;
+                    }
+
+                    part_changed[part] = true;
+                    (part, offset)
+                }
+            };
+
+            positions[i] = (part, offset);
+            part_ends[part] = offset + entry.size;
+        }
+
+        // Unchanged parts keep original size, including any padding at end
+        let part_sizes = part_ends
+            .iter()
+            .enumerate()
+            .map(|(i, end)| match self.part_sizes.get(i) {
+                Some(size) if !part_changed[i] && size >= end => *size,
+                _ => align_to_block(*end),
+            })
+            .collect();
+
+        (positions, part_sizes, part_changed)
+    }
+
+    fn write_header(&self, positions: &[(usize, u64)], part_sizes: &[u64]) -> Result<Vec<u8>, Error> {
+        let mut writer = std::io::Cursor::new(Vec::new());
+
+        writer.write_u32(ARK_VERSION)?;
+        writer.write_u32(part_sizes.len() as u32)?;
+
+        for (i, part_size) in part_sizes.iter().enumerate() {
+            let part_size = u32::try_from(*part_size)
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Ark part {i} is too large ({part_size} bytes)")))?;
+
+            writer.write_u32(part_size)?;
+        }
+
+        writer.write_string_bytes(&self.strings.data)?;
+        writer.write_u32(self.strings.offsets.len() as u32)?;
+
+        for offset in self.strings.offsets.iter() {
+            writer.write_u32(*offset)?;
+        }
+
+        let part_starts = part_sizes
+            .iter()
+            .scan(0, |start, size| {
+                let part_start = *start;
+                *start += size;
+                Some(part_start)
+            })
+            .collect::<Vec<_>>();
+
+        writer.write_u32(self.entries.len() as u32)?;
+
+        for (index, (entry, (part, part_offset))) in self.entries.iter().zip(positions.iter()).enumerate() {
+            let offset = part_starts[*part] + part_offset;
+
+            let offset = u32::try_from(offset)
+                .map_err(|_| Error::InvalidArkEntry { index, message: format!("Offset {offset} is too large") })?;
+
+            let size = u32::try_from(entry.size)
+                .map_err(|_| Error::InvalidArkEntry { index, message: format!("Size {} is too large", entry.size) })?;
+
+            writer.write_u32(offset)?;
+            writer.write_u32(entry.name_index)?;
+            writer.write_u32(entry.dir_index)?;
+            writer.write_u32(size)?;
+            writer.write_u32(entry.inflated_size)?;
+        }
+
+        Ok(writer.into_inner())
+    }
+}
+
+/// String table of header, null terminated strings referenced by index
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ArkStrings {
+    pub data: Vec<u8>,
+    pub offsets: Vec<u32>,
+}
+
+impl ArkStrings {
+    pub fn get(&self, index: u32) -> Option<String> {
+        let offset = *self.offsets.get(index as usize)? as usize;
+
+        let data = self.data.get(offset..).unwrap_or_default();
+        let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+
+        Some(String::from_utf8_lossy(&data[..end]).to_string())
+    }
+
+    /// Gets index of string, adding it if missing
+    pub fn find_or_add(&mut self, value: &str) -> u32 {
+        let existing = (0..self.offsets.len() as u32)
+            .find(|i| self.get(*i).is_some_and(|s| s.eq(value)));
+
+        if let Some(index) = existing {
+            return index;
+        }
+
+        self.offsets.push(self.data.len() as u32);
+        self.data.extend(value.as_bytes());
+        self.data.push(0);
+
+        self.offsets.len() as u32 - 1
+    }
+}
+
+// Gets part and offset in part for data, if data fits in single part
+fn find_part(part_sizes: &[u64], offset: u64, size: u64) -> Option<(usize, u64)> {
+    let mut part_start = 0;
+
+    for (i, part_size) in part_sizes.iter().enumerate() {
+        if offset < part_start + part_size || (size == 0 && offset == part_start + part_size) {
+            let part_offset = offset - part_start;
+
+            return (part_offset + size <= *part_size)
+                .then_some((i, part_offset));
+        }
+
+        part_start += part_size;
+    }
+
+    None
+}
+
+/// Path of .ark part next to .hdr, matching case of header extension
+pub fn ark_part_path<T: AsRef<Path>>(hdr_path: T, part: usize) -> PathBuf {
+    let hdr_path = hdr_path.as_ref();
+
+    let stem = hdr_path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let ark_ext = match hdr_path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.chars().all(|c| c.is_ascii_uppercase()) => "ARK",
+        _ => "ark",
+    };
+
+    hdr_path.with_file_name(format!("{stem}_{part}.{ark_ext}"))
+}
+
+fn align_to_block(offset: u64) -> u64 {
+    offset.div_ceil(ARK_BLOCK_SIZE) * ARK_BLOCK_SIZE
+}
+
+fn write_padding<T: Write>(writer: &mut T, size: u64) -> Result<(), Error> {
+    std::io::copy(&mut std::io::repeat(0).take(size), writer)?;
+    Ok(())
+}
+
+// Copies gap from same place in source part if there is one, zero padding for anything past its end
+fn write_gap<T: Write>(writer: &mut T, source: Option<&mut (File, u64)>, pos: u64, size: u64) -> Result<(), Error> {
+    let mut copied = 0;
+
+    if let Some((file, part_start)) = source {
+        file.seek(SeekFrom::Start(*part_start + pos))?;
+        copied = std::io::copy(&mut file.take(size), writer)?;
+    }
+
+    write_padding(writer, size - copied)
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect()
+    }
+
+    // Version 2 header with one part, strings "a.bnk" and "songs", then entries
+    fn header(version: u32, entry_count: u32, entries: &[[u32; 5]]) -> Vec<u8> {
+        let strings = b"a.bnk\0songs\0";
+
+        [
+            u32s(&[version, 1, 0x1000, strings.len() as u32]),
+            strings.to_vec(),
+            u32s(&[2, 0, 6, entry_count]),
+            entries.iter().flat_map(|e| u32s(e)).collect(),
+        ].concat()
+    }
+
+    #[test]
+    fn reads_version_2_header() {
+        let ark = Ark::from_reader(&mut Cursor::new(header(2, 1, &[[0x800, 0, 1, 0x100, 0]]))).unwrap();
+
+        assert_eq!(ark.part_sizes, [0x1000]);
+        assert_eq!(ark.entries.len(), 1);
+        assert_eq!(ark.entries[0].path(), "songs/a.bnk");
+        assert_eq!((ark.entries[0].part, ark.entries[0].part_offset, ark.entries[0].size), (0, 0x800, 0x100));
+    }
+
+    #[test]
+    fn other_versions_are_unsupported() {
+        for version in [1, 3, 4] {
+            let err = Ark::from_reader(&mut Cursor::new(header(version, 0, &[]))).unwrap_err();
+
+            assert!(matches!(err, Error::UnsupportedArkVersion { version: v } if v == version), "{err:?}");
+        }
+    }
+
+    #[test]
+    fn entry_count_past_header_errors() {
+        let err = Ark::from_reader(&mut Cursor::new(header(2, u32::MAX, &[[0x800, 0, 1, 0x100, 0]]))).unwrap_err();
+
+        assert!(matches!(err, Error::IO { ref source, .. } if source.kind() == std::io::ErrorKind::UnexpectedEof), "{err:?}");
+    }
+
+    #[test]
+    fn string_table_past_header_errors() {
+        let mut data = header(2, 0, &[]);
+        data[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let err = Ark::from_reader(&mut Cursor::new(data)).unwrap_err();
+
+        assert!(matches!(err, Error::IO { ref source, .. } if source.kind() == std::io::ErrorKind::UnexpectedEof), "{err:?}");
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("amp_ark_{name}_{}", std::process::id()));
+
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        dir
+    }
+
+    fn write_files(dir: &Path, files: &[(&str, Vec<u8>)]) {
+        for (path, data) in files {
+            let file_path = dir.join(path);
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, data).unwrap();
+        }
+    }
+
+    fn read_archive_files(hdr_path: &Path, part_count: usize) -> Vec<Vec<u8>> {
+        std::iter::once(hdr_path.to_path_buf())
+            .chain((0..part_count).map(|i| ark_part_path(hdr_path, i)))
+            .map(|p| std::fs::read(p).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn extract_and_pack_rebuilds_archive_exactly() {
+        let root = test_dir("rebuild");
+        let source_dir = root.join("source");
+        let extract_dir = root.join("extract");
+
+        write_files(&source_dir, &[
+            ("songs/tut0/tut0.bnk", vec![0x11; 100]),
+            ("songs/tut0/tut0.nse", (0..3000).map(|i| i as u8).collect()),
+            ("readme.txt", b"amplitude".to_vec()),
+        ]);
+
+        let hdr_path = root.join("MAIN.HDR");
+        let ark = ArkBuilder::from_dir(&source_dir)
+            .unwrap()
+            .with_max_part_size(0x1000)
+            .write_to_files(&hdr_path)
+            .unwrap();
+
+        assert_eq!(ark.part_sizes.len(), 2);
+
+        // Gaps in retail archives aren't always zeros
+        for (part, part_path) in ark.part_paths.iter().enumerate() {
+            let mut data = std::fs::read(part_path).unwrap();
+            let mut filled = vec![true; data.len()];
+
+            for entry in ark.entries.iter().filter(|e| e.part == part) {
+                let start = entry.part_offset as usize;
+                filled[start..(start + entry.size as usize)].fill(false);
+            }
+
+            for (b, _) in data.iter_mut().zip(filled).filter(|(_, f)| *f) {
+                *b = 0xCD;
+            }
+
+            std::fs::write(part_path, data).unwrap();
+        }
+
+        for entry in ark.entries.iter() {
+            ark.extract_entry(entry, extract_dir.join(entry.output_path())).unwrap();
+        }
+
+        let mut builder = ArkBuilder::from_ark(&ark);
+        assert_eq!(builder.add_dir(&extract_dir).unwrap(), 3);
+
+        let rebuilt_hdr_path = root.join("rebuilt").join("MAIN.HDR");
+        let rebuilt = builder.write_to_files(&rebuilt_hdr_path).unwrap();
+
+        let original_files = read_archive_files(&hdr_path, 2);
+        let rebuilt_files = read_archive_files(&rebuilt_hdr_path, rebuilt.part_sizes.len());
+
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(rebuilt_files, original_files);
+    }
+}
@@ -1,9 +1,24 @@
-use crate::SimpleReader;
-use std::io::{Error as IOError, Read, Seek, SeekFrom};
+use crate::{create_output_dir, ChunkTag, Error, SimpleReader, SimpleWriter};
+use crate::naming::SampleNaming;
+use crate::vag::*;
+use crate::wav::WavWriter;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::Path;
 
-const VAG_BYTES_PER_BLOCK: usize = 16;
-const VAG_SAMPLES_PER_BLOCK: usize = 28;
+// Size of fixed entries, not including size field
+const SAMP_ENTRY_SIZE: u32 = 18;
+const BANK_ENTRY_SIZE: u32 = 9;
+const INST_ENTRY_SIZE: u32 = 12;
+const SDES_ENTRY_BASE_SIZE: u32 = 26; // Followed by end data
+
+// Default chunk order used when writing banks without a known layout
+const CHUNK_ORDER: [ChunkTag; 9] = [
+    *b"SAMP", *b"SANM", *b"SAFN",
+    *b"BANK", *b"BKNM",
+    *b"INST", *b"INNM",
+    *b"SDES", *b"SDNM",
+];
 
 #[derive(Debug, Default)]
 pub struct SampleEntry {
@@ -11,62 +26,150 @@ pub struct SampleEntry {
     pub file_name: String,
     pub channels: u32,
     pub sample_rate: u32,
+    pub unknown: [u8; 6],
     pub pos: u32,
+
+    // Not stored in .bnk, found from vag block flags (see BankFile::update_loop_points)
+    pub loop_start: Option<u32>,
+    pub loop_end: Option<u32>, // Exclusive
+
+    // Not stored in .bnk, found from vag block flags (see BankFile::measure_samples)
+    pub end_pos: Option<u32>, // Exclusive, includes all channels
+    pub block_count: Option<u32>, // Per channel
+    pub frame_count: Option<u32>,
+}
+
+impl SampleEntry {
+    /// Length of sample in seconds, needs measured frame count
+    pub fn duration_secs(&self) -> Option<f64> {
+        self.frame_count
+            .filter(|_| self.sample_rate > 0)
+            .map(|f| f as f64 / self.sample_rate as f64)
+    }
+
+    /// Size of sample data in .nse, needs measured end offset
+    pub fn data_size(&self) -> Option<u32> {
+        self.end_pos.map(|e| e - self.pos)
+    }
+
+    pub(crate) fn update_measure(&mut self, index: usize, sample_data: &[u8]) -> Result<(), Error> {
+        let channels = self.channels.max(1) as usize;
+        let interleave_size = sample_interleave_size(sample_data, channels, index, self.pos as u64)?;
+        let block_count = count_vag_blocks(channel_blocks(sample_data, channels, interleave_size, 0));
+
+        self.end_pos = Some(self.pos + find_sample_data_len(sample_data, channels, interleave_size) as u32);
+        self.block_count = Some(block_count as u32);
+        self.frame_count = Some((block_count * VAG_SAMPLES_PER_BLOCK) as u32);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DecodedSample {
+    pub pcm: Vec<i16>, // Interleaved
+    pub loop_start: Option<u32>,
+    pub loop_end: Option<u32>, // Exclusive
 }
 
 #[derive(Debug, Default)]
 pub struct BankEntry {
     pub name: String,
+    pub unknown_1: [u8; 4],
     pub bank_num: u8,
+    pub unknown_2: [u8; 2],
     pub inst_count: u8,
+    pub unknown_3: u8,
 }
 
 #[derive(Debug, Default)]
 pub struct InstEntry {
     pub name: String,
+    pub unknown_1: u32, // Always 1?
     pub prog: u16,
-    pub sdes: u16,
+    pub unknown_2: [u8; 4],
+    pub sdes: u16, // Number of sdes entries owned, see BankFile::inst_sdes_range
 }
 
-#[derive(Debug)]
+const PAN_CENTER: u8 = 0x40;
+const PAN_MAX: u8 = 0x7F;
+
+/// Rough side of stereo field, see `SdesEntry::pan` for exact value
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[repr(u8)]
 pub enum SdesPan {
     Left = 0x0,
+    #[default]
     Center = 0x40,
     Right = 0x7F,
 }
 
-impl Default for SdesPan {
-    fn default() -> Self {
-        SdesPan::Center
-    }
-}
-
 impl From<u8> for SdesPan {
     fn from(num: u8) -> Self {
         match num {
-            0x0 => Self::Left,
+            0x0..=0x3F => Self::Left,
             0x40 => Self::Center,
-            0x7F => Self::Right,
-            // Default
-            // TODO: Maybe panic?
-            _ => Self::default(),
+            _ => Self::Right,
         }
     }
 }
 
-#[derive(Debug, Default)]
+/// Key zone of inst, plays sample over pitch range
+///
+/// Envelope (SPU2 ADSR) and fine tune aren't decoded. They're likely in `unknown_1` but the layout
+/// hasn't been confirmed against retail banks, so the bytes are only kept to write banks back as-is.
+#[derive(Debug)]
 pub struct SdesEntry {
     pub name: String,
 
     pub min_pitch: u8,
     pub max_pitch: u8,
     pub base_pitch: u8,
-    pub transpose: u8,
+    pub transpose: u8, // Signed semitones
+
+    pub unknown_1: [u8; 12], // Not decoded, see above
 
     pub vol: u8,
-    pub pan: SdesPan,
+    pub pan: u8, // 0-127, 64 is center
     pub samp: u8,
+
+    pub unknown_2: [u8; 3],
+    pub end_data: Vec<u8>,
+}
+
+impl Default for SdesEntry {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            min_pitch: 0,
+            max_pitch: 0,
+            base_pitch: 0,
+            transpose: 0,
+            unknown_1: [0u8; 12],
+            vol: 0,
+            pan: PAN_CENTER,
+            samp: 0,
+            unknown_2: [0u8; 3],
+            end_data: Vec::new(),
+        }
+    }
+}
+
+impl SdesEntry {
+    /// Stereo position from -1.0 (left) to 1.0 (right)
+    pub fn pan_position(&self) -> f32 {
+        let pan = self.pan.min(PAN_MAX);
+
+        match pan.cmp(&PAN_CENTER) {
+            std::cmp::Ordering::Less => (pan as f32 - PAN_CENTER as f32) / PAN_CENTER as f32,
+            std::cmp::Ordering::Equal => 0.0,
+            std::cmp::Ordering::Greater => (pan - PAN_CENTER) as f32 / (PAN_MAX - PAN_CENTER) as f32,
+        }
+    }
+
+    pub fn pan_kind(&self) -> SdesPan {
+        self.pan.into()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -75,143 +178,570 @@ pub struct BankFile {
     pub banks: Vec<BankEntry>,
     pub insts: Vec<InstEntry>,
     pub sdes: Vec<SdesEntry>,
+    pub layout: Vec<ChunkLayout>,
+    pub padding: Vec<u8>,
+}
+
+/// Chunk data outside of entries, preserved to write banks back in original layout
+#[derive(Debug, Default)]
+pub struct ChunkLayout {
+    pub tag: ChunkTag,
+    pub string_header: Option<u32>, // Only for name chunks, always 1?
+    pub strings: Option<Vec<String>>, // Only for name chunks read from file, count may not match entries
+    pub trailing_data: Vec<u8>,
 }
 
 impl BankFile {
-    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, IOError> {
-        // TODO: Make this work with generic stream
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
         let mut bnk_file = std::fs::OpenOptions::new()
             .read(true)
-            .open(path)
-            .unwrap();
+            .open(path)?;
+
+        Self::from_reader(&mut bnk_file)
+    }
+
+    pub fn from_reader<T: Read + Seek>(reader: &mut T) -> Result<Self, Error> {
+        let start_pos = reader.stream_position()?;
+        let stream_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start_pos))?;
 
         let mut magic = [0u8; 4];
 
         let mut bank = Self::default();
 
-        while let Ok(_) = bnk_file.read_bytes(&mut magic) {
-            match &magic {
-                b"SAMP" => {
-                    bank.read_samples(&mut bnk_file)?;
-                },
-                b"SANM" => {
-                    let strings = bank.read_strings(&mut bnk_file)?;
-
-                    // Update sample names
-                    for (i, str) in strings.into_iter().enumerate() {
-                        if let Some(sam) = bank.samples.get_mut(i) {
-                            sam.name = str;
-                        }
-                    }
-                },
-                b"SAFN" => {
-                    let strings = bank.read_strings(&mut bnk_file)?;
-
-                    // Update sample file names
-                    for (i, str) in strings.into_iter().enumerate() {
-                        if let Some(sam) = bank.samples.get_mut(i) {
-                            sam.file_name = str;
-                        }
-                    }
-                },
-                b"BANK" => {
-                    bank.read_banks(&mut bnk_file)?;
-                },
-                b"BKNM" => {
-                    let strings = bank.read_strings(&mut bnk_file)?;
-
-                    // Update bank names
-                    for (i, str) in strings.into_iter().enumerate() {
-                        if let Some(bnk) = bank.banks.get_mut(i) {
-                            bnk.name = str;
-                        }
-                    }
-                },
-                b"INST" => {
-                    bank.read_insts(&mut bnk_file)?;
-                },
-                b"INNM" => {
-                    let strings = bank.read_strings(&mut bnk_file)?;
-
-                    // Update inst names
-                    for (i, str) in strings.into_iter().enumerate() {
-                        if let Some(inst) = bank.insts.get_mut(i) {
-                            inst.name = str;
-                        }
-                    }
-                },
-                b"SDES" => {
-                    bank.read_sdes(&mut bnk_file)?;
-                },
-                b"SDNM" => {
-                    let strings = bank.read_strings(&mut bnk_file)?;
-
-                    // Update sdes names
-                    for (i, str) in strings.into_iter().enumerate() {
-                        if let Some(sdes) = bank.sdes.get_mut(i) {
-                            sdes.name = str;
-                        }
-                    }
-                },
-                _ => break
+        while reader.stream_position()? < stream_len {
+            let chunk_offset = reader.stream_position()?;
+
+            // Too short for chunk magic, only allowed as padding
+            if stream_len - chunk_offset < magic.len() as u64 {
+                let mut tail = Vec::new();
+                reader.read_to_end(&mut tail)?;
+
+                if tail.iter().any(|b| *b != 0) {
+                    magic[..tail.len()].copy_from_slice(&tail);
+
+                    return Err(Error::TruncatedChunk {
+                        tag: magic,
+                        offset: chunk_offset,
+                        expected: 8,
+                        found: tail.len() as u64,
+                    });
+                }
+
+                bank.padding = tail;
+                break;
+            }
+
+            reader.read_bytes(&mut magic)?;
+
+            if magic == [0u8; 4] {
+                // Padding at end of file
+                reader.seek(SeekFrom::Start(chunk_offset))?;
+                reader.read_to_end(&mut bank.padding)?;
+                break;
+            }
+
+            let chunk_size = reader.read_u32()
+                .map_err(|e| Error::from(e).with_chunk(magic, chunk_offset, 8, stream_len))? as u64;
+            reader.seek(SeekFrom::Current(-4))?;
+
+            if chunk_offset + 8 + chunk_size > stream_len {
+                return Err(Error::TruncatedChunk {
+                    tag: magic,
+                    offset: chunk_offset,
+                    expected: chunk_size + 8,
+                    found: stream_len - chunk_offset,
+                });
+            }
+
+            bank.read_chunk(reader, magic, chunk_offset)
+                .map_err(|e| e.with_chunk(magic, chunk_offset, chunk_size + 8, stream_len))?;
+
+            // Preserve any remaining bytes in chunk
+            let chunk_end = chunk_offset + 8 + chunk_size;
+            let current_pos = reader.stream_position()?;
+
+            if let Some(layout) = bank.layout.last_mut().filter(|_| current_pos < chunk_end) {
+                layout.trailing_data = vec![0u8; (chunk_end - current_pos) as usize];
+                reader.read_exact(&mut layout.trailing_data)?;
             }
         }
 
+        bank.apply_chunk_names();
+
         Ok(bank)
     }
 
-    pub fn extract_samples_to_dir<T: AsRef<Path>, S: AsRef<Path>>(&self, sample_file_path: T, output_dir_path: S) -> Result<(), IOError> {
+    /// Sets entry names from strings of name chunks, extra strings are only kept in layout
+    fn apply_chunk_names(&mut self) {
+        for chunk in self.layout.iter() {
+            let Some(strings) = chunk.strings.as_ref() else {
+                continue;
+            };
+
+            let names: Vec<&mut String> = match &chunk.tag {
+                b"SANM" => self.samples.iter_mut().map(|s| &mut s.name).collect(),
+                b"SAFN" => self.samples.iter_mut().map(|s| &mut s.file_name).collect(),
+                b"BKNM" => self.banks.iter_mut().map(|b| &mut b.name).collect(),
+                b"INNM" => self.insts.iter_mut().map(|i| &mut i.name).collect(),
+                b"SDNM" => self.sdes.iter_mut().map(|s| &mut s.name).collect(),
+                _ => continue,
+            };
+
+            for (name, str) in names.into_iter().zip(strings.iter()) {
+                *name = str.to_owned();
+            }
+        }
+    }
+
+    /// Current names of entries stored in name chunk
+    fn entry_names(&self, tag: &ChunkTag) -> Vec<&str> {
+        match tag {
+            b"SANM" => self.samples.iter().map(|s| s.name.as_str()).collect(),
+            b"SAFN" => self.samples.iter().map(|s| s.file_name.as_str()).collect(),
+            b"BKNM" => self.banks.iter().map(|b| b.name.as_str()).collect(),
+            b"INNM" => self.insts.iter().map(|i| i.name.as_str()).collect(),
+            b"SDNM" => self.sdes.iter().map(|s| s.name.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn read_chunk<T: SimpleReader>(&mut self, reader: &mut T, magic: ChunkTag, offset: u64) -> Result<(), Error> {
+        self.layout.push(ChunkLayout {
+            tag: magic,
+            ..Default::default()
+        });
+
+        match &magic {
+            b"SAMP" => {
+                self.read_samples(reader)?;
+            },
+            b"SANM" | b"SAFN" | b"BKNM" | b"INNM" | b"SDNM" => {
+                // Applied to entries after all chunks are read, name chunk can come before its entries
+                let strings = self.read_strings(reader, magic)?;
+
+                if let Some(layout) = self.layout.last_mut() {
+                    layout.strings = Some(strings);
+                }
+            },
+            b"BANK" => {
+                self.read_banks(reader)?;
+            },
+            b"INST" => {
+                self.read_insts(reader)?;
+            },
+            b"SDES" => {
+                self.read_sdes(reader)?;
+            },
+            _ => return Err(Error::BadChunkMagic {
+                tag: magic,
+                offset,
+            })
+        }
+
+        Ok(())
+    }
+
+    /// Decodes samples and writes as .wav files to directory, named by naming policy
+    ///
+    /// Loop points found while decoding are kept, same as `update_loop_points`
+    pub fn extract_samples_to_dir<T: AsRef<Path>, S: AsRef<Path>>(&mut self, sample_file_path: T, output_dir_path: S, naming: &SampleNaming) -> Result<(), Error> {
         let mut sample_file = std::fs::OpenOptions::new()
             .read(true)
-            .open(sample_file_path)
-            .unwrap();
+            .open(sample_file_path)?;
 
+        self.extract_samples_from_reader(&mut sample_file, output_dir_path, naming)
+    }
+
+    /// Extracts samples from .nse stream (i.e. entry in archive) to directory
+    pub fn extract_samples_from_reader<T: Read + Seek, S: AsRef<Path>>(&mut self, sample_reader: &mut T, output_dir_path: S, naming: &SampleNaming) -> Result<(), Error> {
         let output_dir = output_dir_path.as_ref();
 
-        if !output_dir.exists() {
-            std::fs::create_dir_all(output_dir)?;
+        create_output_dir(output_dir)?;
+
+        let file_names = naming.file_names(self);
+
+        for (i, file_name) in file_names.iter().enumerate() {
+            let (loop_start, loop_end) = self.extract_sample(sample_reader, &output_dir.join(file_name), i)?;
+
+            let sample = &mut self.samples[i];
+            sample.loop_start = loop_start;
+            sample.loop_end = loop_end;
         }
 
-        for sample in self.samples.iter() {
-            let output_path = output_dir.join(format!("{}.wav", sample.name));
+        Ok(())
+    }
+
+    /// Extracts samples like `extract_samples_to_dir`, decoding and writing across threads of current rayon pool
+    ///
+    /// The .nse file is memory-mapped so threads don't share a reader. Output doesn't depend on thread count.
+    pub fn extract_samples_to_dir_parallel<T: AsRef<Path>, S: AsRef<Path>>(&mut self, sample_file_path: T, output_dir_path: S, naming: &SampleNaming) -> Result<(), Error> {
+        let sample_file = std::fs::File::open(sample_file_path)?;
+
+        // Empty files can't be mapped
+        // SAFETY: .nse is only read from and shouldn't be modified by anything else while extracting
+        let sample_map = match sample_file.metadata()?.len() {
+            0 => None,
+            _ => Some(unsafe { memmap2::Mmap::map(&sample_file)? }),
+        };
+
+        self.extract_samples_from_slice_parallel(sample_map.as_deref().unwrap_or_default(), output_dir_path, naming)
+    }
+
+    /// Extracts samples from .nse data already in memory, across threads of current rayon pool
+    pub fn extract_samples_from_slice_parallel<S: AsRef<Path>>(&mut self, sample_data: &[u8], output_dir_path: S, naming: &SampleNaming) -> Result<(), Error> {
+        use rayon::prelude::*;
+
+        let output_dir = output_dir_path.as_ref();
+
+        create_output_dir(output_dir)?;
+
+        let loop_points = naming
+            .file_names(self)
+            .par_iter()
+            .enumerate()
+            .map(|(i, file_name)| self.extract_sample(&mut std::io::Cursor::new(sample_data), &output_dir.join(file_name), i))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for (sample, (loop_start, loop_end)) in self.samples.iter_mut().zip(loop_points) {
+            sample.loop_start = loop_start;
+            sample.loop_end = loop_end;
+        }
+
+        Ok(())
+    }
+
+    /// Writes sample as .wav, returning loop points found while decoding
+    fn extract_sample<T: Read + Seek>(&self, sample_reader: &mut T, output_path: &Path, index: usize) -> Result<(Option<u32>, Option<u32>), Error> {
+        let sample = &self.samples[index];
+        let decoded = self.decode_sample(sample_reader, index)?;
 
-            let mut decoder = grim::audio::VAGDecoder::new();
-            let mut vag_block = [0u8; VAG_BYTES_PER_BLOCK];
-            sample_file.seek(SeekFrom::Start(sample.pos as u64))?;
+        // Create wav file
+        let mut wav = WavWriter::new(decoded.pcm.as_slice(), sample.channels as u16, sample.sample_rate);
 
-            let mut sample_stream = Vec::new();
+        if let (Some(start), Some(end)) = (decoded.loop_start, decoded.loop_end) {
+            wav = wav.with_loop(start, end);
+        }
+
+        wav.encode_to_file(output_path)
+            .map_err(|e| match e {
+                Error::IO { source, .. } => Error::Encoder {
+                    index,
+                    offset: sample.pos as u64,
+                    path: output_path.to_path_buf(),
+                    source,
+                },
+                e => e,
+            })?;
+
+        Ok((decoded.loop_start, decoded.loop_end))
+    }
+
+    /// Decodes sample from .nse stream into PCM, along with loop points from block flags
+    ///
+    /// Multi-channel samples are decoded per channel and interleaved (see `find_interleave_size`)
+    pub fn decode_sample<T: Read + Seek>(&self, sample_reader: &mut T, index: usize) -> Result<DecodedSample, Error> {
+        let mut stream = self.sample_stream(&mut *sample_reader, index)?;
+        let mut decoded = DecodedSample::default();
+
+        if let Some(frame_count) = self.samples[index].frame_count {
+            decoded.pcm.reserve(frame_count as usize * stream.channels());
+        }
+
+        while let Some(block) = stream.next_block()? {
+            decoded.pcm.extend_from_slice(block);
+        }
+
+        decoded.loop_start = stream.loop_start();
+        decoded.loop_end = stream.loop_end();
+
+        Ok(decoded)
+    }
+
+    /// Decodes sample like `decode_sample`, mixing down multi-channel audio to mono
+    pub fn decode_sample_mono<T: Read + Seek>(&self, sample_reader: &mut T, index: usize) -> Result<DecodedSample, Error> {
+        let mut decoded = self.decode_sample(sample_reader, index)?;
+        let channels = self.samples[index].channels.max(1) as usize;
+
+        if channels > 1 {
+            decoded.pcm = decoded.pcm
+                .chunks_exact(channels)
+                .map(|frame| (frame.iter().map(|s| *s as i32).sum::<i32>() / channels as i32) as i16)
+                .collect();
+        }
+
+        Ok(decoded)
+    }
+
+    /// Fills in end offset, block count and frame count of samples from vag block flags in .nse stream, without decoding
+    pub fn measure_samples<T: Read + Seek>(&mut self, sample_reader: &mut T) -> Result<(), Error> {
+        for i in 0..self.samples.len() {
+            let sample_data = self.read_sample_data(sample_reader, i)?;
+            self.samples[i].update_measure(i, &sample_data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Range of sample in .nse data, up to start of next sample or end of data
+    ///
+    /// Sample data can't extend past start of next sample, anything between end blocks and next sample is padding
+    pub(crate) fn sample_data_range(&self, index: usize, sample_data_len: u64) -> Result<Range<u64>, Error> {
+        let Some(sample) = self.samples.get(index).filter(|s| (s.pos as u64) < sample_data_len) else {
+            return Err(Error::SampleOffsetOutOfRange {
+                tag: *b"SAMP",
+                offset: self.samples.get(index).map(|s| s.pos as u64).unwrap_or_default(),
+                index,
+                size: sample_data_len,
+            });
+        };
+
+        let start = sample.pos as u64;
+
+        let end = self.samples
+            .iter()
+            .map(|s| s.pos as u64)
+            .filter(|p| *p > start)
+            .min()
+            .unwrap_or(sample_data_len);
 
-            // Read until 0x07 flag or EOF
-            while sample_file.read_exact(&mut vag_block).is_ok() && vag_block[1] != 0x07 {
-                // Decode block into samples
-                let decoded_samples = decoder.decode_block(&vag_block);
-                sample_stream.append(&mut decoded_samples.to_vec());
+        Ok(start..end)
+    }
+
+    /// Reads raw .nse data of sample, up to start of next sample or end of stream
+    fn read_sample_data<T: Read + Seek>(&self, sample_reader: &mut T, index: usize) -> Result<Vec<u8>, Error> {
+        let sample_file_len = sample_reader.seek(SeekFrom::End(0))?;
+        let range = self.sample_data_range(index, sample_file_len)?;
+
+        let mut sample_data = vec![0u8; (range.end - range.start) as usize];
+        sample_reader.seek(SeekFrom::Start(range.start))?;
+        sample_reader.read_exact(&mut sample_data)?;
+
+        Ok(sample_data)
+    }
+
+    /// Fills in loop points of samples from vag block flags in .nse stream
+    pub fn update_loop_points<T: Read + Seek>(&mut self, sample_reader: &mut T) -> Result<(), Error> {
+        for i in 0..self.samples.len() {
+            self.update_sample_loop_points(sample_reader, i)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fills in loop points of sample from vag block flags, after encoding these are block aligned so can differ from source
+    pub(crate) fn update_sample_loop_points<T: Read + Seek>(&mut self, sample_reader: &mut T, index: usize) -> Result<(), Error> {
+        let decoded = self.decode_sample(sample_reader, index)?;
+
+        let sample = &mut self.samples[index];
+        sample.loop_start = decoded.loop_start;
+        sample.loop_end = decoded.loop_end;
+
+        Ok(())
+    }
+
+    /// Re-encodes sample and splices it into .nse data, shifting positions of samples stored after it
+    ///
+    /// Other entries sharing data of sample (same pos) are updated too, since they play the new data
+    pub fn replace_sample(&mut self, sample_data: &mut Vec<u8>, index: usize, pcm: &[i16], sample_rate: u32, channels: u16, loop_points: Option<(u32, u32)>) -> Result<(), Error> {
+        let range = self.sample_data_range(index, sample_data.len() as u64)?;
+        let (start, next_pos) = (range.start as usize, range.end as usize);
+
+        // Only old sample data is replaced, padding before next sample is kept
+        let old_data = &sample_data[start..next_pos];
+        let old_channels = self.samples[index].channels.max(1) as usize;
+        let old_interleave_size = sample_interleave_size(old_data, old_channels, index, range.start)?;
+        let end = (start + find_sample_data_len(old_data, old_channels, old_interleave_size)).min(next_pos);
+
+        let (encoded, encoded_channels) = encode_sample_data(pcm, channels, loop_points);
+        let new_end = start + encoded.len();
+
+        sample_data.splice(start..end, encoded);
+
+        // Only samples stored after replaced one move, old data can be empty (end == start)
+        for s in self.samples.iter_mut().filter(|s| s.pos as usize > start) {
+            s.pos = (s.pos as usize + new_end - end) as u32;
+            s.end_pos = s.end_pos.map(|e| (e as usize + new_end - end) as u32);
+        }
+
+        let aliases = (0..self.samples.len())
+            .filter(|i| self.samples[*i].pos as usize == start)
+            .collect::<Vec<_>>();
+
+        for i in aliases {
+            let sample = &mut self.samples[i];
+            sample.sample_rate = sample_rate;
+            sample.channels = encoded_channels;
+            sample.update_measure(i, &sample_data[start..new_end])?;
+
+            self.update_sample_loop_points(&mut std::io::Cursor::new(sample_data.as_slice()), i)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_to_file<T: AsRef<Path>>(&self, path: T) -> Result<(), Error> {
+        let mut bnk_file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        self.write_to(&mut bnk_file)
+    }
+
+    pub fn write_to<T: Write + Seek>(&self, writer: &mut T) -> Result<(), Error> {
+        let default_layout = CHUNK_ORDER
+            .iter()
+            .map(|tag| ChunkLayout {
+                tag: *tag,
+                string_header: None,
+                strings: None,
+                trailing_data: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        let layout = match self.layout.is_empty() {
+            true => &default_layout,
+            false => &self.layout,
+        };
+
+        for chunk in layout.iter() {
+            let chunk_offset = writer.stream_position()?;
+
+            writer.write_bytes(&chunk.tag)?;
+            writer.write_u32(0)?; // Size, updated after writing entries
+
+            match &chunk.tag {
+                b"SAMP" => self.write_samples(writer)?,
+                b"BANK" => self.write_banks(writer)?,
+                b"INST" => self.write_insts(writer)?,
+                b"SDES" => self.write_sdes(writer)?,
+                b"SANM" | b"SAFN" | b"BKNM" | b"INNM" | b"SDNM" => self.write_names(writer, chunk)?,
+                _ => return Err(Error::BadChunkMagic {
+                    tag: chunk.tag,
+                    offset: chunk_offset,
+                })
             }
 
-            // Create wav file
-            let wav = grim::audio::WavEncoder::new(sample_stream.as_slice(), sample.channels as u16, sample.sample_rate);
-            wav.encode_to_file(output_path).unwrap(); // TODO: Properly handle error
+            writer.write_all(&chunk.trailing_data)?;
+
+            // Go back and update chunk size
+            let chunk_end = writer.stream_position()?;
+            writer.seek(SeekFrom::Start(chunk_offset + 4))?;
+            writer.write_u32((chunk_end - chunk_offset - 8) as u32)?;
+            writer.seek(SeekFrom::Start(chunk_end))?;
+        }
+
+        writer.write_all(&self.padding)?;
+
+        Ok(())
+    }
+
+    fn write_samples<T: SimpleWriter>(&self, writer: &mut T) -> Result<(), Error> {
+        for sample in self.samples.iter() {
+            writer.write_u32(SAMP_ENTRY_SIZE)?;
+            writer.write_u32(sample.channels)?;
+            writer.write_u32(sample.sample_rate)?;
+            writer.write_bytes(&sample.unknown)?;
+            writer.write_u32(sample.pos)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes entry names, name chunks read from file keep any strings without entries
+    fn write_names<T: SimpleWriter>(&self, writer: &mut T, chunk: &ChunkLayout) -> Result<(), Error> {
+        let mut names = self.entry_names(&chunk.tag);
+
+        let strings = match chunk.strings.as_ref() {
+            Some(strings) => {
+                // Entries past string count without name weren't in file, so aren't written
+                let name_count = names.iter().rposition(|n| !n.is_empty()).map_or(0, |i| i + 1);
+                names.truncate(name_count.max(strings.len()));
+
+                let extra_strings = strings.iter().skip(names.len()).map(|s| s.as_str());
+                names.into_iter().chain(extra_strings).collect()
+            },
+            None => names,
+        };
+
+        writer.write_u32(chunk.string_header.unwrap_or(1))?;
+
+        for str in strings {
+            writer.write_string_bytes(str.as_bytes())?;
         }
 
         Ok(())
     }
 
-    fn read_samples<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), IOError> {
+    fn write_banks<T: SimpleWriter>(&self, writer: &mut T) -> Result<(), Error> {
+        for bank in self.banks.iter() {
+            writer.write_u32(BANK_ENTRY_SIZE)?;
+            writer.write_bytes(&bank.unknown_1)?;
+            writer.write_u8(bank.bank_num)?;
+            writer.write_bytes(&bank.unknown_2)?;
+            writer.write_u8(bank.inst_count)?;
+            writer.write_u8(bank.unknown_3)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_insts<T: SimpleWriter>(&self, writer: &mut T) -> Result<(), Error> {
+        for inst in self.insts.iter() {
+            writer.write_u32(INST_ENTRY_SIZE)?;
+            writer.write_u32(inst.unknown_1)?;
+            writer.write_u16(inst.prog)?;
+            writer.write_bytes(&inst.unknown_2)?;
+            writer.write_u16(inst.sdes)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_sdes<T: SimpleWriter>(&self, writer: &mut T) -> Result<(), Error> {
+        for sdes in self.sdes.iter() {
+            writer.write_u32(SDES_ENTRY_BASE_SIZE + sdes.end_data.len() as u32)?;
+            writer.write_u32(sdes.end_data.len() as u32)?;
+
+            writer.write_u8(sdes.min_pitch)?;
+            writer.write_u8(sdes.max_pitch)?;
+            writer.write_u8(sdes.base_pitch)?;
+            writer.write_u8(sdes.transpose)?;
+
+            writer.write_bytes(&sdes.unknown_1)?;
+
+            writer.write_u8(sdes.vol)?;
+            writer.write_u8(sdes.pan)?;
+            writer.write_u8(sdes.samp)?;
+
+            writer.write_bytes(&sdes.unknown_2)?;
+            writer.write_all(&sdes.end_data)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_samples<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), Error> {
         let size = reader.read_u32()?;
-        let entry_count = size / 22;
+        let entry_count = size / (SAMP_ENTRY_SIZE + 4);
 
         for _ in 0..entry_count {
-            reader.seek(SeekFrom::Current(4))?; // Entry size, always 18
+            read_entry_size(reader, *b"SAMP", SAMP_ENTRY_SIZE)?;
 
             let channels = reader.read_u32()?;
             let sample_rate = reader.read_u32()?;
 
-            reader.seek(SeekFrom::Current(6))?;
+            let mut unknown = [0u8; 6];
+            reader.read_bytes(&mut unknown)?;
+
             let pos = reader.read_u32()?;
 
             self.samples.push(SampleEntry {
                 channels,
                 sample_rate,
+                unknown,
                 pos,
                 ..Default::default()
             });
@@ -220,39 +750,83 @@ impl BankFile {
         Ok(())
     }
 
-    fn read_strings<T: SimpleReader>(&mut self, reader: &mut T) -> Result<Vec<String>, IOError> {
-        let size = reader.read_u32()?;
-        let end_pos = reader.stream_position()? + size as u64;
+    fn read_strings<T: SimpleReader>(&mut self, reader: &mut T, tag: ChunkTag) -> Result<Vec<String>, Error> {
+        let size = reader.read_u32()? as u64;
+        let chunk_offset = reader.stream_position()? - 8;
+        let end_pos = chunk_offset + 8 + size.min(reader.remaining_len()?);
+
+        // Chunk needs room for string header
+        if end_pos < chunk_offset + 12 {
+            return Err(Error::TruncatedChunk {
+                tag,
+                offset: chunk_offset,
+                expected: 12,
+                found: end_pos - chunk_offset,
+            });
+        }
+
+        let header = reader.read_u32()?; // Always 1?
 
-        reader.seek(SeekFrom::Current(4))?; // Always 1?
+        if let Some(layout) = self.layout.last_mut() {
+            layout.string_header = Some(header);
+        }
 
         let mut strings = Vec::new();
 
         while reader.stream_position()? < end_pos {
-            let str = reader.read_string()?;
+            let offset = reader.stream_position()?;
+            let str_size = reader.read_u32()? as u64;
+
+            // String can't extend past end of chunk
+            if offset + 4 + str_size > end_pos {
+                return Err(Error::TruncatedChunk {
+                    tag,
+                    offset: chunk_offset,
+                    expected: offset + 4 + str_size - chunk_offset,
+                    found: end_pos - chunk_offset,
+                });
+            }
+
+            reader.seek(SeekFrom::Start(offset))?;
+            let data = reader.read_string_bytes()?;
+
+            let str = String::from_utf8(data)
+                .map_err(|e| Error::InvalidString {
+                    tag,
+                    offset,
+                    source: e,
+                })?;
+
             strings.push(str);
         }
 
         Ok(strings)
     }
 
-    fn read_banks<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), IOError> {
+    fn read_banks<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), Error> {
         let size = reader.read_u32()?;
-        let entry_count = size / 13;
+        let entry_count = size / (BANK_ENTRY_SIZE + 4);
 
         for _ in 0..entry_count {
-            reader.seek(SeekFrom::Current(4))?; // Entry size, always 9
-            reader.seek(SeekFrom::Current(4))?; // Unknown
+            read_entry_size(reader, *b"BANK", BANK_ENTRY_SIZE)?;
+
+            let mut unknown_1 = [0u8; 4];
+            reader.read_bytes(&mut unknown_1)?;
 
             let bank_num = reader.read_u8()?;
-            reader.seek(SeekFrom::Current(2))?; // Unknown
+
+            let mut unknown_2 = [0u8; 2];
+            reader.read_bytes(&mut unknown_2)?;
 
             let inst_count = reader.read_u8()?;
-            reader.seek(SeekFrom::Current(1))?; // Unknown
+            let unknown_3 = reader.read_u8()?;
 
             self.banks.push(BankEntry {
+                unknown_1,
                 bank_num,
+                unknown_2,
                 inst_count,
+                unknown_3,
                 ..Default::default()
             });
         }
@@ -260,21 +834,25 @@ impl BankFile {
         Ok(())
     }
 
-    fn read_insts<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), IOError> {
+    fn read_insts<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), Error> {
         let size = reader.read_u32()?;
-        let entry_count = size / 16;
+        let entry_count = size / (INST_ENTRY_SIZE + 4);
 
         for _ in 0..entry_count {
-            reader.seek(SeekFrom::Current(4))?; // Entry size, always 12
-            reader.seek(SeekFrom::Current(4))?; // Unknown, always 1?
+            read_entry_size(reader, *b"INST", INST_ENTRY_SIZE)?;
 
+            let unknown_1 = reader.read_u32()?;
             let prog = reader.read_u16()?;
-            reader.seek(SeekFrom::Current(4))?; // Unknown
+
+            let mut unknown_2 = [0u8; 4];
+            reader.read_bytes(&mut unknown_2)?;
 
             let sdes = reader.read_u16()?;
 
             self.insts.push(InstEntry {
+                unknown_1,
                 prog,
+                unknown_2,
                 sdes,
                 ..Default::default()
             });
@@ -283,36 +861,64 @@ impl BankFile {
         Ok(())
     }
 
-    fn read_sdes<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), IOError> {
-        let size = reader.read_u32()?;
-        let end_pos = reader.stream_position()? + size as u64;
+    fn read_sdes<T: SimpleReader>(&mut self, reader: &mut T) -> Result<(), Error> {
+        let size = reader.read_u32()? as u64;
+        let chunk_offset = reader.stream_position()? - 8;
+        let end_pos = chunk_offset + 8 + size.min(reader.remaining_len()?);
 
         while reader.stream_position()? < end_pos {
-            reader.seek(SeekFrom::Current(4))?; // Entry size
-
+            let entry_offset = reader.stream_position()?;
+            let entry_size = reader.read_u32()?;
             let end_bytes = reader.read_u32()?;
 
+            // End data must fill rest of entry and stay within chunk
+            if entry_size as u64 != SDES_ENTRY_BASE_SIZE as u64 + end_bytes as u64 {
+                return Err(Error::InvalidEntrySize {
+                    tag: *b"SDES",
+                    offset: entry_offset,
+                    expected: SDES_ENTRY_BASE_SIZE as u64 + end_bytes as u64,
+                    found: entry_size as u64,
+                });
+            }
+
+            if entry_offset + 4 + entry_size as u64 > end_pos {
+                return Err(Error::TruncatedChunk {
+                    tag: *b"SDES",
+                    offset: chunk_offset,
+                    expected: entry_offset + 4 + entry_size as u64 - chunk_offset,
+                    found: end_pos - chunk_offset,
+                });
+            }
+
             let min_pitch = reader.read_u8()?;
             let max_pitch = reader.read_u8()?;
             let base_pitch = reader.read_u8()?;
             let transpose = reader.read_u8()?;
 
-            reader.seek(SeekFrom::Current(12))?; // Unknown
+            let mut unknown_1 = [0u8; 12];
+            reader.read_bytes(&mut unknown_1)?;
 
             let vol = reader.read_u8()?;
-            let pan = reader.read_u8()?.into();
+            let pan = reader.read_u8()?;
             let samp = reader.read_u8()?;
 
-            reader.seek(SeekFrom::Current(3 + end_bytes as i64))?;
+            let mut unknown_2 = [0u8; 3];
+            reader.read_bytes(&mut unknown_2)?;
+
+            let mut end_data = vec![0u8; end_bytes as usize];
+            reader.read_exact(&mut end_data)?;
 
             self.sdes.push(SdesEntry {
                 min_pitch,
                 max_pitch,
                 base_pitch,
                 transpose,
+                unknown_1,
                 vol,
                 pan,
                 samp,
+                unknown_2,
+                end_data,
                 ..Default::default()
             });
         }
@@ -320,3 +926,634 @@ impl BankFile {
         Ok(())
     }
 }
+
+/// Reads size of fixed entry, erroring if it doesn't match known layout
+fn read_entry_size<T: SimpleReader>(reader: &mut T, tag: ChunkTag, expected: u32) -> Result<(), Error> {
+    let offset = reader.stream_position()?;
+    let entry_size = reader.read_u32()?;
+
+    if entry_size != expected {
+        return Err(Error::InvalidEntrySize {
+            tag,
+            offset,
+            expected: expected as u64,
+            found: entry_size as u64,
+        });
+    }
+
+    Ok(())
+}
+
+/// Encodes interleaved pcm to .nse sample data, returning data and its channel count
+///
+/// Multi-channel audio is stored as full channel streams back to back, see `find_interleave_size`.
+pub(crate) fn encode_sample_data(pcm: &[i16], channels: u16, loop_points: Option<(u32, u32)>) -> (Vec<u8>, u32) {
+    let channels = channels.max(1) as usize;
+
+    if channels == 1 {
+        return (encode_vag(pcm, loop_points), 1);
+    }
+
+    let sample_data = (0..channels)
+        .flat_map(|c| {
+            let channel_pcm = pcm
+                .chunks_exact(channels)
+                .map(|frame| frame[c])
+                .collect::<Vec<_>>();
+
+            encode_vag(&channel_pcm, loop_points)
+        })
+        .collect();
+
+    (sample_data, channels as u32)
+}
+
+/// Iterates vag blocks of channel in sample data, see `sample_interleave_size`
+fn channel_blocks(sample_data: &[u8], channels: usize, interleave_size: usize, channel: usize) -> impl Iterator<Item = &[u8]> {
+    sample_data
+        .chunks(interleave_size * channels)
+        .flat_map(move |row| row
+            .chunks(interleave_size)
+            .nth(channel)
+            .unwrap_or_default()
+            .chunks_exact(VAG_BYTES_PER_BLOCK))
+}
+
+/// Counts vag blocks played until 0x07 flag or end block, same as decoded by `SampleStream`
+fn count_vag_blocks<'a, T: Iterator<Item = &'a [u8]>>(blocks: T) -> usize {
+    let mut count = 0;
+
+    for vag_block in blocks {
+        let flags = vag_block[1];
+
+        if flags == VAG_FLAG_STREAM_END {
+            break;
+        }
+
+        count += 1;
+
+        if (flags & VAG_FLAG_LOOP_END) != 0 {
+            break;
+        }
+    }
+
+    count
+}
+
+/// Common interleave sizes for PS2 audio, smallest first
+const INTERLEAVE_SIZES: [usize; 8] = [0x10, 0x80, 0x100, 0x200, 0x400, 0x800, 0x1000, 0x2000];
+
+/// Bytes per channel chunk of sample data, whole data for mono samples
+///
+/// Layout of multi-channel data is guessed by `find_interleave_size`. If no layout matches,
+/// `Error::UnknownSampleLayout` is returned since decoding with a wrong guess gives noise.
+pub(crate) fn sample_interleave_size(sample_data: &[u8], channels: usize, index: usize, offset: u64) -> Result<usize, Error> {
+    match channels {
+        1 => Ok(sample_data.len().max(VAG_BYTES_PER_BLOCK)),
+        _ if sample_data.is_empty() => Ok(VAG_BYTES_PER_BLOCK),
+        _ => find_interleave_size(sample_data, channels).ok_or(Error::UnknownSampleLayout { index, offset, channels }),
+    }
+}
+
+/// Finds bytes per channel chunk of multi-channel sample data
+///
+/// Each channel is its own vag stream so end blocks of all channels line up one chunk apart.
+/// Full channel streams stored back to back (needed for SPU2 voices to play from memory) are
+/// checked first, then fixed interleave sizes. Returns `None` if first channel has no end block
+/// or end blocks of other channels aren't found at any of the sizes.
+pub(crate) fn find_interleave_size(sample_data: &[u8], channels: usize) -> Option<usize> {
+    let stream_size = find_sample_data_end(sample_data, 0);
+
+    // Back to back streams, each ending on the same block
+    if stream_size > 0
+        && stream_size * channels <= sample_data.len()
+        && (1..channels).all(|c| find_sample_data_end(&sample_data[(stream_size * c)..], 0) == stream_size) {
+        return Some(stream_size);
+    }
+
+    let first_end = sample_data
+        .chunks_exact(VAG_BYTES_PER_BLOCK)
+        .position(|b| (b[1] & VAG_FLAG_LOOP_END) != 0)
+        .map(|i| i * VAG_BYTES_PER_BLOCK)?;
+
+    // Other channels should end with same flags, otherwise 0x07 block after end could match
+    let end_flags = sample_data[first_end + 1];
+
+    INTERLEAVE_SIZES
+        .iter()
+        .copied()
+        .find(|size| (1..channels).all(|c| sample_data.get(first_end + (size * c) + 1) == Some(&end_flags)))
+}
+
+/// Finds size of sample data including all channels, interleave size from `sample_interleave_size`
+fn find_sample_data_len(sample_data: &[u8], channels: usize, interleave_size: usize) -> usize {
+    if channels == 1 {
+        return find_sample_data_end(sample_data, 0);
+    }
+
+    let row_size = interleave_size * channels;
+
+    // Rows up to and including last end block of first channel
+    let mut rows = sample_data
+        .chunks(row_size)
+        .position(|row| row[..interleave_size.min(row.len())]
+            .chunks_exact(VAG_BYTES_PER_BLOCK)
+            .any(|b| (b[1] & VAG_FLAG_LOOP_END) != 0))
+        .map(|r| r + 1)
+        .unwrap_or(sample_data.len().div_ceil(row_size)); // Only empty data, layout is found from end block
+
+    // Along with rows of 0x07 blocks after
+    while sample_data.get((rows * row_size) + 1) == Some(&VAG_FLAG_STREAM_END) {
+        rows += 1;
+    }
+
+    (rows * row_size).min(sample_data.len())
+}
+
+/// Finds end of vag stream starting at offset, including trailing 0x07 block
+fn find_sample_data_end(sample_data: &[u8], start: usize) -> usize {
+    let mut offset = start;
+
+    while offset + VAG_BYTES_PER_BLOCK <= sample_data.len() {
+        let flags = sample_data[offset + 1];
+        offset += VAG_BYTES_PER_BLOCK;
+
+        if flags == VAG_FLAG_STREAM_END {
+            break;
+        }
+
+        if (flags & VAG_FLAG_LOOP_END) != 0 {
+            // End block is usually followed by 0x07 block
+            if sample_data.get(offset + 1) == Some(&VAG_FLAG_STREAM_END) {
+                offset += VAG_BYTES_PER_BLOCK;
+            }
+
+            break;
+        }
+    }
+
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::*;
+    use std::io::Cursor;
+
+    fn chunk(tag: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [tag.as_slice(), &(data.len() as u32).to_le_bytes(), data].concat()
+    }
+
+    fn strings(header: u32, strs: &[&str]) -> Vec<u8> {
+        let mut data = header.to_le_bytes().to_vec();
+
+        for str in strs {
+            data.extend_from_slice(&(str.len() as u32).to_le_bytes());
+            data.extend_from_slice(str.as_bytes());
+        }
+
+        data
+    }
+
+    fn sdes_entry(end_bytes: u32, entry_size: u32, end_data: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&entry_size.to_le_bytes());
+        data.extend_from_slice(&end_bytes.to_le_bytes());
+        data.extend_from_slice(&[36, 72, 60, 0xFE]); // Pitch range, base pitch and transpose
+        data.extend_from_slice(&[0xF4, 1, 2, 3, 4, 5, 6, 7, 0x0F, 0x80, 0xDF, 0x5F]);
+        data.extend_from_slice(&[100, 0x20, 1, 9, 8, 7]);
+        data.extend_from_slice(end_data);
+        data
+    }
+
+    // Every chunk with non-zero unknown bytes, trailing chunk data and padding
+    fn test_bank_data() -> Vec<u8> {
+        let mut samp = Vec::new();
+
+        for (channels, pos) in [(1u32, 0u32), (2, 0x40)] {
+            samp.extend_from_slice(&18u32.to_le_bytes());
+            samp.extend_from_slice(&channels.to_le_bytes());
+            samp.extend_from_slice(&22050u32.to_le_bytes());
+            samp.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+            samp.extend_from_slice(&pos.to_le_bytes());
+        }
+
+        let mut bank = 9u32.to_le_bytes().to_vec();
+        bank.extend_from_slice(&[0xA, 0xB, 0xC, 0xD, 3, 0xE, 0xF, 2, 0x10]);
+        bank.extend_from_slice(&[0xAA, 0xBB]); // Trailing data
+
+        let mut inst = Vec::new();
+
+        for (prog, sdes) in [(1u16, 1u16), (2, 1)] {
+            inst.extend_from_slice(&12u32.to_le_bytes());
+            inst.extend_from_slice(&1u32.to_le_bytes());
+            inst.extend_from_slice(&prog.to_le_bytes());
+            inst.extend_from_slice(&[4, 3, 2, 1]);
+            inst.extend_from_slice(&sdes.to_le_bytes());
+        }
+
+        let sdes = [sdes_entry(0, 26, &[]), sdes_entry(3, 29, &[0xC0, 0xFF, 0xEE])].concat();
+
+        [
+            chunk(b"SAMP", &samp),
+            chunk(b"SANM", &strings(1, &["kick", "pad"])),
+            chunk(b"SAFN", &strings(1, &["kick.vag", "pad.vag"])),
+            chunk(b"BANK", &bank),
+            chunk(b"BKNM", &strings(1, &["drums"])),
+            chunk(b"INST", &inst),
+            chunk(b"INNM", &strings(2, &["kick", "pad"])),
+            chunk(b"SDES", &sdes),
+            chunk(b"SDNM", &strings(1, &["kick", "pad"])),
+            vec![0u8; 6],
+        ].concat()
+    }
+
+    fn tone(len: usize, period: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| if (i / period) & 1 == 0 { 8000 } else { -8000 })
+            .collect()
+    }
+
+    // Mono samples stored back to back, with gap of padding after last one
+    fn test_sample_bank(pcms: &[Vec<i16>], gap: usize) -> (BankFile, Vec<u8>) {
+        let mut bank = BankFile::default();
+        let mut sample_data = Vec::new();
+
+        for pcm in pcms {
+            bank.samples.push(SampleEntry {
+                channels: 1,
+                sample_rate: 22050,
+                pos: sample_data.len() as u32,
+                ..Default::default()
+            });
+
+            sample_data.extend(encode_sample_data(pcm, 1, None).0);
+        }
+
+        sample_data.extend(vec![0u8; gap]);
+        bank.measure_samples(&mut Cursor::new(&sample_data)).unwrap();
+
+        (bank, sample_data)
+    }
+
+    fn decoded_pcm(bank: &BankFile, sample_data: &[u8], index: usize) -> Vec<i16> {
+        bank.decode_sample(&mut Cursor::new(sample_data), index).unwrap().pcm
+    }
+
+    #[test]
+    fn replace_sample_shifts_later_samples() {
+        let pcms = [tone(280, 5), tone(560, 7), tone(140, 3), tone(280, 11)];
+
+        for new_len in [56, 560, 1400] {
+            let (mut bank, mut sample_data) = test_sample_bank(&pcms, 0);
+            let old_pos = bank.samples.iter().map(|s| s.pos).collect::<Vec<_>>();
+            let old_pcm = (0..pcms.len()).map(|i| decoded_pcm(&bank, &sample_data, i)).collect::<Vec<_>>();
+
+            let old_size = (old_pos[2] - old_pos[1]) as i64;
+            let new_pcm = tone(new_len, 13);
+
+            bank.replace_sample(&mut sample_data, 1, &new_pcm, 44100, 1, None).unwrap();
+
+            let new_size = encode_sample_data(&new_pcm, 1, None).0.len() as i64;
+            let delta = new_size - old_size;
+
+            assert_eq!(bank.samples[0].pos, old_pos[0]);
+            assert_eq!(bank.samples[1].pos, old_pos[1]);
+            assert_eq!(bank.samples[1].sample_rate, 44100);
+
+            for (i, (sample, pos)) in bank.samples.iter().zip(old_pos.iter()).enumerate().skip(2) {
+                assert_eq!(sample.pos as i64, *pos as i64 + delta, "sample {i} pos");
+            }
+
+            assert_eq!(decoded_pcm(&bank, &sample_data, 1).len(), new_len.div_ceil(VAG_SAMPLES_PER_BLOCK) * VAG_SAMPLES_PER_BLOCK);
+
+            for i in [0, 2, 3] {
+                assert_eq!(decoded_pcm(&bank, &sample_data, i), old_pcm[i], "sample {i} data");
+            }
+        }
+    }
+
+    #[test]
+    fn replace_empty_sample_keeps_own_position() {
+        let (mut bank, mut sample_data) = test_sample_bank(&[tone(280, 5), tone(280, 7)], 0);
+
+        // Sample with less than one block of data before next sample
+        let empty_pos = bank.samples[1].pos;
+        sample_data.splice((empty_pos as usize)..(empty_pos as usize), [0u8; 8]);
+        bank.samples[1].pos += 8;
+
+        bank.samples.insert(1, SampleEntry {
+            channels: 1,
+            sample_rate: 22050,
+            pos: empty_pos,
+            ..Default::default()
+        });
+
+        let next_pos = bank.samples[2].pos;
+        let new_pcm = tone(280, 13);
+        let new_size = encode_sample_data(&new_pcm, 1, None).0.len() as u32;
+
+        bank.replace_sample(&mut sample_data, 1, &new_pcm, 22050, 1, None).unwrap();
+
+        assert_eq!(bank.samples[1].pos, empty_pos);
+        assert_eq!(bank.samples[2].pos, next_pos + new_size);
+    }
+
+    #[test]
+    fn replace_sample_updates_entries_sharing_data() {
+        let (mut bank, mut sample_data) = test_sample_bank(&[tone(280, 5), tone(280, 7)], 0);
+
+        // Entry reusing data of first sample
+        bank.samples.push(SampleEntry {
+            name: String::from("alias"),
+            channels: 1,
+            sample_rate: 22050,
+            pos: bank.samples[0].pos,
+            ..Default::default()
+        });
+
+        let next_pos = bank.samples[1].pos;
+        let new_pcm = tone(560, 13);
+        let new_size = encode_sample_data(&new_pcm, 1, Some((56, 448))).0.len() as u32;
+
+        bank.replace_sample(&mut sample_data, 0, &new_pcm, 44100, 1, Some((56, 448))).unwrap();
+
+        assert_eq!(bank.samples[1].pos, new_size);
+        assert_ne!(bank.samples[1].pos, next_pos);
+
+        let (sample, alias) = (&bank.samples[0], &bank.samples[2]);
+        assert_eq!(alias.pos, sample.pos);
+        assert_eq!(alias.sample_rate, 44100);
+        assert_eq!((alias.end_pos, alias.frame_count), (sample.end_pos, sample.frame_count));
+        assert_eq!((alias.loop_start, alias.loop_end), (Some(56), Some(448)));
+        assert_eq!(decoded_pcm(&bank, &sample_data, 2), decoded_pcm(&bank, &sample_data, 0));
+    }
+
+    #[test]
+    fn write_round_trips_bytes() {
+        let data = test_bank_data();
+        let bank = BankFile::from_reader(&mut Cursor::new(&data)).unwrap();
+
+        assert_eq!(bank.samples.len(), 2);
+        assert_eq!(bank.insts.len(), 2);
+        assert_eq!(bank.sdes[1].end_data, [0xC0, 0xFF, 0xEE]);
+        assert_eq!(bank.layout[3].trailing_data, [0xAA, 0xBB]);
+
+        let mut written = Cursor::new(Vec::new());
+        bank.write_to(&mut written).unwrap();
+
+        assert_eq!(written.into_inner(), data);
+    }
+
+    // Name chunks with more (SANM) and fewer (SAFN) strings than sample entries
+    fn mismatched_name_chunks() -> Vec<u8> {
+        let mut samp = Vec::new();
+
+        for pos in [0u32, 0x40] {
+            samp.extend_from_slice(&18u32.to_le_bytes());
+            samp.extend_from_slice(&1u32.to_le_bytes());
+            samp.extend_from_slice(&22050u32.to_le_bytes());
+            samp.extend_from_slice(&[0u8; 6]);
+            samp.extend_from_slice(&pos.to_le_bytes());
+        }
+
+        let mut bank = 9u32.to_le_bytes().to_vec();
+        bank.extend_from_slice(&[0, 0, 0, 0, 3, 0, 0, 0, 0]);
+
+        [
+            chunk(b"BKNM", &strings(1, &["drums"])), // Before its entries
+            chunk(b"BANK", &bank),
+            chunk(b"SAMP", &samp),
+            chunk(b"SANM", &strings(1, &["kick", "snare", "extra"])),
+            chunk(b"SAFN", &strings(1, &["kick.vag"])),
+        ].concat()
+    }
+
+    #[test]
+    fn name_chunks_with_mismatched_counts_round_trip() {
+        let data = mismatched_name_chunks();
+        let mut bank = BankFile::from_reader(&mut Cursor::new(&data)).unwrap();
+
+        assert_eq!(bank.banks[0].name, "drums");
+        assert_eq!(bank.samples[1].name, "snare");
+        assert_eq!(bank.samples[1].file_name, "");
+
+        let mut written = Cursor::new(Vec::new());
+        bank.write_to(&mut written).unwrap();
+        assert_eq!(written.into_inner(), data);
+
+        // Renamed entries are written, strings without entries are kept
+        bank.samples[0].name = String::from("kick2");
+
+        let mut written = Cursor::new(Vec::new());
+        bank.write_to(&mut written).unwrap();
+
+        let renamed = BankFile::from_reader(&mut Cursor::new(written.into_inner())).unwrap();
+        assert_eq!(renamed.samples[0].name, "kick2");
+        assert_eq!(renamed.layout[3].strings.as_deref(), Some(&[String::from("kick2"), String::from("snare"), String::from("extra")][..]));
+        assert_eq!(renamed.layout[4].strings.as_ref().map(|s| s.len()), Some(1));
+    }
+
+    #[test]
+    fn names_added_after_reading_are_written() {
+        let mut bank = BankFile::from_reader(&mut Cursor::new(mismatched_name_chunks())).unwrap();
+        bank.samples[1].file_name = String::from("snare.vag");
+
+        let mut written = Cursor::new(Vec::new());
+        bank.write_to(&mut written).unwrap();
+
+        let named = BankFile::from_reader(&mut Cursor::new(written.into_inner())).unwrap();
+        assert_eq!(named.samples[0].file_name, "kick.vag");
+        assert_eq!(named.samples[1].file_name, "snare.vag");
+        assert_eq!(named.samples[1].name, "snare");
+    }
+
+    #[test]
+    fn name_chunk_without_string_header_is_truncated() {
+        let data = [chunk(b"SANM", &[1, 0]), chunk(b"SAFN", &strings(1, &[]))].concat();
+        let err = BankFile::from_reader(&mut Cursor::new(&data)).unwrap_err();
+
+        assert!(matches!(err, Error::TruncatedChunk { tag, offset: 0, found: 10, .. } if &tag == b"SANM"), "{err:?}");
+    }
+
+    #[test]
+    fn sdes_end_data_past_chunk_is_truncated() {
+        let data = chunk(b"SDES", &sdes_entry(0xFFFF_FF00, 0xFFFF_FF1A, &[]));
+        let err = BankFile::from_reader(&mut Cursor::new(&data)).unwrap_err();
+
+        assert!(matches!(err, Error::TruncatedChunk { tag, .. } if &tag == b"SDES"), "{err:?}");
+    }
+
+    #[test]
+    fn sdes_entry_size_mismatch_errors() {
+        let data = chunk(b"SDES", &sdes_entry(3, 26, &[0, 0, 0]));
+        let err = BankFile::from_reader(&mut Cursor::new(&data)).unwrap_err();
+
+        assert!(matches!(err, Error::InvalidEntrySize { expected: 29, found: 26, .. }), "{err:?}");
+    }
+
+    #[test]
+    fn string_size_past_chunk_is_truncated() {
+        let mut data = strings(1, &["kick"]);
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let err = BankFile::from_reader(&mut Cursor::new(chunk(b"SANM", &data))).unwrap_err();
+
+        assert!(matches!(err, Error::TruncatedChunk { tag, .. } if &tag == b"SANM"), "{err:?}");
+    }
+
+    fn stereo_pcm(len: usize) -> Vec<i16> {
+        tone(len, 5)
+            .into_iter()
+            .zip(tone(len, 9))
+            .flat_map(|(l, r)| [l, r])
+            .collect()
+    }
+
+    fn channel_streams(pcm: &[i16]) -> Vec<Vec<u8>> {
+        (0..2)
+            .map(|c| encode_vag(&pcm.iter().skip(c).step_by(2).copied().collect::<Vec<_>>(), None))
+            .collect()
+    }
+
+    // Channel streams split into chunks of interleave size, last chunk padded with zeros
+    fn interleave(streams: &[Vec<u8>], size: usize) -> Vec<u8> {
+        let rows = streams.iter().map(|s| s.len().div_ceil(size)).max().unwrap_or_default();
+
+        (0..rows)
+            .flat_map(|r| streams.iter().flat_map(move |s| {
+                let mut chunk = s.iter().skip(r * size).take(size).copied().collect::<Vec<_>>();
+                chunk.resize(size, 0);
+                chunk
+            }))
+            .collect()
+    }
+
+    fn stereo_bank(sample_data: &[u8]) -> Result<BankFile, Error> {
+        let mut bank = BankFile::default();
+
+        bank.samples.push(SampleEntry {
+            channels: 2,
+            sample_rate: 22050,
+            ..Default::default()
+        });
+
+        bank.measure_samples(&mut Cursor::new(sample_data))?;
+        Ok(bank)
+    }
+
+    #[test]
+    fn mono_sample_data_ends_after_end_block() {
+        let pcm = tone(280, 5);
+        let (bank, sample_data) = test_sample_bank(std::slice::from_ref(&pcm), 64);
+
+        assert_eq!(bank.samples[0].data_size(), Some(encode_vag(&pcm, None).len() as u32));
+        assert_eq!(bank.samples[0].frame_count, Some(280));
+        assert_eq!(decoded_pcm(&bank, &sample_data, 0).len(), 280);
+    }
+
+    #[test]
+    fn back_to_back_stereo_layout_is_found() {
+        let (sample_data, channels) = encode_sample_data(&stereo_pcm(560), 2, None);
+        assert_eq!(channels, 2);
+
+        assert_eq!(find_interleave_size(&sample_data, 2), Some(sample_data.len() / 2));
+
+        let mut padded = sample_data.clone();
+        padded.extend([0u8; 64]);
+
+        let bank = stereo_bank(&padded).unwrap();
+        assert_eq!(bank.samples[0].data_size(), Some(sample_data.len() as u32));
+        assert_eq!(bank.samples[0].frame_count, Some(560));
+    }
+
+    #[test]
+    fn interleaved_stereo_layout_is_found() {
+        let pcm = stereo_pcm(560);
+        let (back_to_back, _) = encode_sample_data(&pcm, 2, None);
+        let expected = decoded_pcm(&stereo_bank(&back_to_back).unwrap(), &back_to_back, 0);
+
+        for size in [0x80, 0x800] {
+            let sample_data = interleave(&channel_streams(&pcm), size);
+            assert_eq!(find_interleave_size(&sample_data, 2), Some(size));
+
+            let bank = stereo_bank(&sample_data).unwrap();
+            assert_eq!(bank.samples[0].data_size(), Some(sample_data.len() as u32));
+            assert_eq!(bank.samples[0].frame_count, Some(560));
+            assert_eq!(decoded_pcm(&bank, &sample_data, 0), expected);
+        }
+    }
+
+    #[test]
+    fn unknown_stereo_layout_errors() {
+        // Channels end on different blocks, so neither layout lines up
+        let mut mismatched = encode_vag(&tone(560, 5), None);
+        mismatched.extend(encode_vag(&tone(280, 9), None));
+
+        // No end blocks at all
+        let no_end = vec![0u8; 256];
+
+        for sample_data in [mismatched, no_end] {
+            assert_eq!(find_interleave_size(&sample_data, 2), None);
+
+            let err = stereo_bank(&sample_data).unwrap_err();
+            assert!(matches!(err, Error::UnknownSampleLayout { index: 0, offset: 0, channels: 2 }), "{err:?}");
+        }
+    }
+
+    // Looped sample followed by one-shot, measured but without loop points filled in
+    fn looped_sample_bank() -> (BankFile, Vec<u8>) {
+        let (mut bank, mut sample_data) = looped_bank();
+        push_one_shot_sample(&mut bank, &mut sample_data);
+
+        bank.samples[0].loop_start = None;
+        bank.samples[0].loop_end = None;
+
+        (bank, sample_data)
+    }
+
+    fn loop_points(bank: &BankFile) -> Vec<(Option<u32>, Option<u32>)> {
+        bank.samples
+            .iter()
+            .map(|s| (s.loop_start, s.loop_end))
+            .collect()
+    }
+
+    #[test]
+    fn extracting_samples_fills_loop_points() {
+        let (mut expected, sample_data) = looped_sample_bank();
+        expected.update_loop_points(&mut Cursor::new(&sample_data)).unwrap();
+        assert_eq!(loop_points(&expected), [(Some(LOOP_START), Some(LOOP_END)), (None, None)]);
+
+        let output_dir = std::env::temp_dir().join(format!("amp_extract_loops_{}", std::process::id()));
+        let naming = SampleNaming::default();
+
+        let (mut serial, _) = looped_sample_bank();
+        serial.extract_samples_from_reader(&mut Cursor::new(&sample_data), &output_dir, &naming).unwrap();
+
+        let (mut parallel, _) = looped_sample_bank();
+        parallel.extract_samples_from_slice_parallel(&sample_data, &output_dir, &naming).unwrap();
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        assert_eq!(loop_points(&serial), loop_points(&expected));
+        assert_eq!(loop_points(&parallel), loop_points(&expected));
+    }
+
+    #[test]
+    fn failed_wav_write_reports_sample_and_path() {
+        let (mut bank, sample_data) = looped_sample_bank();
+
+        // Directory in place of second sample's wav so it can't be created
+        let output_dir = std::env::temp_dir().join(format!("amp_extract_encoder_{}", std::process::id()));
+        let blocked_path = output_dir.join("1.wav");
+        std::fs::create_dir_all(&blocked_path).unwrap();
+
+        let err = bank.extract_samples_from_reader(&mut Cursor::new(&sample_data), &output_dir, &SampleNaming::default()).unwrap_err();
+        std::fs::remove_dir_all(&output_dir).unwrap();
+
+        let sample_pos = bank.samples[1].pos as u64;
+        assert!(matches!(err, Error::Encoder { index: 1, offset, ref path, .. } if offset == sample_pos && path == &blocked_path), "{err:?}");
+    }
+}
@@ -0,0 +1,433 @@
+use crate::bank::*;
+use crate::wav::WavData;
+use crate::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Describes banks, instruments and key zones to build from directory of wav files
+#[derive(Debug, Default, Deserialize)]
+pub struct BankManifest {
+    #[serde(default)]
+    pub banks: Vec<ManifestBank>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ManifestBank {
+    #[serde(default)]
+    pub name: String,
+    pub bank_num: u8,
+    #[serde(default)]
+    pub instruments: Vec<ManifestInst>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ManifestInst {
+    #[serde(default)]
+    pub name: String,
+    pub prog: u16,
+    #[serde(default)]
+    pub zones: Vec<ManifestZone>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ManifestZone {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub sample: String, // Wav file in sample directory, extension optional
+    #[serde(default)]
+    pub min_pitch: u8,
+    #[serde(default = "default_max_pitch")]
+    pub max_pitch: u8,
+    #[serde(default = "default_base_pitch")]
+    pub base_pitch: u8,
+    #[serde(default)]
+    pub transpose: i8,
+    #[serde(default = "default_vol")]
+    pub vol: u8,
+    #[serde(default = "default_pan")]
+    pub pan: u8,
+}
+
+fn default_max_pitch() -> u8 {
+    127
+}
+
+fn default_base_pitch() -> u8 {
+    60
+}
+
+fn default_vol() -> u8 {
+    127
+}
+
+fn default_pan() -> u8 {
+    64
+}
+
+impl BankManifest {
+    /// Reads manifest as toml if extension is .toml, otherwise json
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+
+        let is_toml = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("toml"));
+
+        match is_toml {
+            true => Self::from_toml(&text),
+            false => Self::from_json(&text),
+        }
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(text)?)
+    }
+}
+
+/// Builds new .bnk and .nse pair, encoding samples to vag
+///
+/// Banks own insts and insts own zones in order added, so insts are added to last bank and zones to last inst.
+#[derive(Debug, Default)]
+pub struct BankBuilder {
+    bank: BankFile,
+    sample_data: Vec<u8>,
+}
+
+impl BankBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads samples referenced by manifest from directory and adds all banks, insts and zones
+    pub fn from_manifest<T: AsRef<Path>>(manifest: &BankManifest, sample_dir_path: T) -> Result<Self, Error> {
+        let sample_dir = sample_dir_path.as_ref();
+
+        let mut builder = Self::new();
+        let mut sample_indices = HashMap::new();
+
+        for bank in manifest.banks.iter() {
+            builder.add_bank(&bank.name, bank.bank_num);
+
+            for inst in bank.instruments.iter() {
+                builder.add_inst(&inst.name, inst.prog)?;
+
+                for zone in inst.zones.iter() {
+                    let file_name = match Path::new(&zone.sample).extension() {
+                        Some(_) => zone.sample.to_owned(),
+                        None => format!("{}.wav", zone.sample),
+                    };
+
+                    let sample_index = match sample_indices.get(&file_name) {
+                        Some(index) => *index,
+                        None => {
+                            let wav = WavData::from_file(sample_dir.join(&file_name))?;
+
+                            let name = Path::new(&file_name)
+                                .file_stem()
+                                .and_then(|s| s.to_str())
+                                .unwrap_or_default()
+                                .to_owned();
+
+                            let index = builder.add_sample(&name, &file_name, &wav)?;
+                            sample_indices.insert(file_name.to_owned(), index);
+                            index
+                        }
+                    };
+
+                    if sample_index > u8::MAX as usize {
+                        return Err(Error::Manifest("Too many samples, zones can only reference first 256".into()));
+                    }
+
+                    builder.add_zone(SdesEntry {
+                        name: zone.name.to_owned().unwrap_or_else(|| zone.sample.to_owned()),
+                        min_pitch: zone.min_pitch,
+                        max_pitch: zone.max_pitch,
+                        base_pitch: zone.base_pitch,
+                        transpose: zone.transpose as u8,
+                        vol: zone.vol,
+                        pan: zone.pan,
+                        samp: sample_index as u8,
+                        ..Default::default()
+                    })?;
+                }
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Encodes wav to vag and appends to sample data
+    pub fn add_sample(&mut self, name: &str, file_name: &str, wav: &WavData) -> Result<usize, Error> {
+        let (encoded, channels) = encode_sample_data(&wav.samples, wav.channels, wav.loop_points);
+
+        let mut sample = SampleEntry {
+            name: name.to_owned(),
+            file_name: file_name.to_owned(),
+            channels,
+            sample_rate: wav.sample_rate,
+            pos: self.sample_data.len() as u32,
+            ..Default::default()
+        };
+
+        let index = self.bank.samples.len();
+        sample.update_measure(index, &encoded)?;
+        self.sample_data.extend(encoded);
+        self.bank.samples.push(sample);
+
+        self.bank.update_sample_loop_points(&mut std::io::Cursor::new(self.sample_data.as_slice()), index)?;
+
+        Ok(index)
+    }
+
+    pub fn add_bank(&mut self, name: &str, bank_num: u8) -> usize {
+        self.bank.banks.push(BankEntry {
+            name: name.to_owned(),
+            bank_num,
+            ..Default::default()
+        });
+
+        self.bank.banks.len() - 1
+    }
+
+    /// Adds inst to last added bank
+    pub fn add_inst(&mut self, name: &str, prog: u16) -> Result<usize, Error> {
+        let Some(bank) = self.bank.banks.last_mut() else {
+            return Err(Error::Manifest("Instrument added before any bank".into()));
+        };
+
+        if bank.inst_count == u8::MAX {
+            return Err(Error::Manifest(format!("Bank \"{}\" has too many instruments", bank.name)));
+        }
+
+        bank.inst_count += 1;
+
+        self.bank.insts.push(InstEntry {
+            name: name.to_owned(),
+            unknown_1: 1,
+            prog,
+            ..Default::default()
+        });
+
+        Ok(self.bank.insts.len() - 1)
+    }
+
+    /// Adds zone to last added inst, inst stores number of zones it owns
+    pub fn add_zone(&mut self, sdes: SdesEntry) -> Result<usize, Error> {
+        let Some(inst) = self.bank.insts.last_mut() else {
+            return Err(Error::Manifest("Zone added before any instrument".into()));
+        };
+
+        let Some(sdes_count) = inst.sdes.checked_add(1) else {
+            return Err(Error::Manifest(format!("Instrument \"{}\" has too many zones", inst.name)));
+        };
+
+        inst.sdes = sdes_count;
+        self.bank.sdes.push(sdes);
+
+        Ok(self.bank.sdes.len() - 1)
+    }
+
+    /// Returns bank and .nse sample data
+    pub fn build(self) -> (BankFile, Vec<u8>) {
+        (self.bank, self.sample_data)
+    }
+
+    pub fn write_to_files<T: AsRef<Path>, S: AsRef<Path>>(self, bank_path: T, sample_file_path: S) -> Result<BankFile, Error> {
+        let (bank, sample_data) = self.build();
+
+        bank.write_to_file(bank_path)?;
+        std::fs::write(sample_file_path, sample_data)?;
+
+        Ok(bank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::WavWriter;
+    use std::io::Cursor;
+
+    const JSON_MANIFEST: &str = r#"{
+        "banks": [
+            {
+                "name": "drums",
+                "bank_num": 2,
+                "instruments": [
+                    {
+                        "name": "kit",
+                        "prog": 5,
+                        "zones": [
+                            { "sample": "kick", "max_pitch": 59 },
+                            { "name": "snare_hi", "sample": "snare.wav", "min_pitch": 60, "base_pitch": 62, "transpose": -2, "vol": 100, "pan": 20 }
+                        ]
+                    }
+                ]
+            }
+        ]
+    }"#;
+
+    const TOML_MANIFEST: &str = r#"
+        [[banks]]
+        name = "drums"
+        bank_num = 2
+
+        [[banks.instruments]]
+        name = "kit"
+        prog = 5
+
+        [[banks.instruments.zones]]
+        sample = "kick"
+        max_pitch = 59
+
+        [[banks.instruments.zones]]
+        name = "snare_hi"
+        sample = "snare.wav"
+        min_pitch = 60
+        base_pitch = 62
+        transpose = -2
+        vol = 100
+        pan = 20
+    "#;
+
+    fn zone_values(zone: &ManifestZone) -> (Option<&str>, &str, u8, u8, u8, i8, u8, u8) {
+        (zone.name.as_deref(), zone.sample.as_str(), zone.min_pitch, zone.max_pitch, zone.base_pitch, zone.transpose, zone.vol, zone.pan)
+    }
+
+    fn wav(frames: usize, loop_points: Option<(u32, u32)>) -> WavData {
+        WavData {
+            samples: (0..frames).map(|i| ((i % 20) as i16 - 10) * 1000).collect(),
+            channels: 1,
+            sample_rate: 22050,
+            loop_points,
+        }
+    }
+
+    #[test]
+    fn json_and_toml_manifests_match() {
+        for manifest in [BankManifest::from_json(JSON_MANIFEST).unwrap(), BankManifest::from_toml(TOML_MANIFEST).unwrap()] {
+            assert_eq!(manifest.banks.len(), 1);
+            assert_eq!((manifest.banks[0].name.as_str(), manifest.banks[0].bank_num), ("drums", 2));
+
+            let inst = &manifest.banks[0].instruments[0];
+            assert_eq!((inst.name.as_str(), inst.prog), ("kit", 5));
+
+            // Unset values use defaults
+            assert_eq!(zone_values(&inst.zones[0]), (None, "kick", 0, 59, 60, 0, 127, 64));
+            assert_eq!(zone_values(&inst.zones[1]), (Some("snare_hi"), "snare.wav", 60, 127, 62, -2, 100, 20));
+        }
+    }
+
+    #[test]
+    fn invalid_manifest_errors() {
+        let err = BankManifest::from_json(r#"{ "banks": [{ "name": "drums" }] }"#).err().unwrap();
+        assert!(matches!(err, Error::Manifest(_)), "{err:?}");
+
+        let err = BankManifest::from_toml("[[banks]]\nbank_num = \"two\"").err().unwrap();
+        assert!(matches!(err, Error::Manifest(_)), "{err:?}");
+    }
+
+    #[test]
+    fn samples_are_placed_one_after_another() {
+        let mut builder = BankBuilder::new();
+
+        for (i, frames) in [56, 100, 28].into_iter().enumerate() {
+            let index = builder.add_sample(&format!("sample_{i}"), &format!("sample_{i}.wav"), &wav(frames, None)).unwrap();
+            assert_eq!(index, i);
+        }
+
+        let (bank, sample_data) = builder.build();
+
+        let positions = bank.samples
+            .iter()
+            .map(|s| (s.pos, s.end_pos.unwrap()))
+            .collect::<Vec<_>>();
+
+        for pair in positions.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+
+        assert_eq!(positions[0].0, 0);
+        assert_eq!(positions[2].1 as usize, sample_data.len());
+    }
+
+    #[test]
+    fn zones_and_insts_need_owner() {
+        let mut builder = BankBuilder::new();
+
+        assert!(matches!(builder.add_inst("kit", 0), Err(Error::Manifest(_))));
+        assert!(matches!(builder.add_zone(SdesEntry::default()), Err(Error::Manifest(_))));
+    }
+
+    #[test]
+    fn built_bank_reads_back() {
+        let sample_dir = std::env::temp_dir().join(format!("amp_builder_{}", std::process::id()));
+        std::fs::create_dir_all(&sample_dir).unwrap();
+
+        let (kick, snare) = (wav(196, None), wav(280, None));
+
+        WavWriter::new(&kick.samples, 1, 22050)
+            .encode_to_file(sample_dir.join("kick.wav"))
+            .unwrap();
+
+        WavWriter::new(&snare.samples, 1, 22050)
+            .with_loop(56, 224)
+            .encode_to_file(sample_dir.join("snare.wav"))
+            .unwrap();
+
+        let manifest = BankManifest::from_json(JSON_MANIFEST).unwrap();
+        let builder = BankBuilder::from_manifest(&manifest, &sample_dir);
+        std::fs::remove_dir_all(&sample_dir).unwrap();
+
+        let (built, sample_data) = builder.unwrap().build();
+
+        let mut bank_data = Cursor::new(Vec::new());
+        built.write_to(&mut bank_data).unwrap();
+        bank_data.set_position(0);
+
+        let mut bank = BankFile::from_reader(&mut bank_data).unwrap();
+        bank.measure_samples(&mut Cursor::new(&sample_data)).unwrap();
+        bank.update_loop_points(&mut Cursor::new(&sample_data)).unwrap();
+
+        let banks = bank.banks.iter().map(|b| (b.name.as_str(), b.bank_num, b.inst_count)).collect::<Vec<_>>();
+        assert_eq!(banks, [("drums", 2, 1)]);
+
+        let insts = bank.insts.iter().map(|i| (i.name.as_str(), i.prog, i.sdes)).collect::<Vec<_>>();
+        assert_eq!(insts, [("kit", 5, 2)]);
+
+        let zones = bank.sdes
+            .iter()
+            .map(|z| (z.name.as_str(), z.min_pitch, z.max_pitch, z.base_pitch, z.transpose as i8, z.vol, z.pan, z.samp))
+            .collect::<Vec<_>>();
+
+        assert_eq!(zones, [
+            ("kick", 0, 59, 60, 0, 127, 64, 0),
+            ("snare_hi", 60, 127, 62, -2, 100, 20, 1),
+        ]);
+
+        // Sample data and loop points as built
+        let samples = bank.samples
+            .iter()
+            .map(|s| (s.name.as_str(), s.file_name.as_str(), s.pos, s.end_pos, s.loop_start, s.loop_end))
+            .collect::<Vec<_>>();
+
+        let built_samples = built.samples
+            .iter()
+            .map(|s| (s.name.as_str(), s.file_name.as_str(), s.pos, s.end_pos, s.loop_start, s.loop_end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(samples, built_samples);
+        assert_eq!(samples[0].4, None);
+        assert_eq!((samples[1].4, samples[1].5), (Some(56), Some(224)));
+
+        let decoded = bank.decode_sample(&mut Cursor::new(&sample_data), 0).unwrap();
+        assert_eq!(decoded.pcm.len(), kick.samples.len());
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Error as IOError, ErrorKind};
+use std::path::PathBuf;
+use std::string::FromUtf8Error;
+use thiserror::Error as ThisError;
+
+pub type ChunkTag = [u8; 4];
+
+// Displays chunk tag as text (e.g. SAMP) or ? if unknown
+struct TagDisplay<'a>(pub &'a Option<ChunkTag>);
+
+impl Display for TagDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.0 {
+            Some(tag) => write!(f, "{}", String::from_utf8_lossy(tag)),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("I/O error in chunk {} at offset {offset:?}: {source}", TagDisplay(.tag))]
+    IO {
+        tag: Option<ChunkTag>,
+        offset: Option<u64>,
+        source: IOError,
+    },
+    #[error("Bad chunk magic {:?} at offset {offset}", String::from_utf8_lossy(.tag))]
+    BadChunkMagic {
+        tag: ChunkTag,
+        offset: u64,
+    },
+    #[error("Chunk {} at offset {offset} is truncated (expected {expected} bytes, found {found})", String::from_utf8_lossy(.tag))]
+    TruncatedChunk {
+        tag: ChunkTag,
+        offset: u64,
+        expected: u64,
+        found: u64,
+    },
+    #[error("Entry in chunk {} at offset {offset} has size {found} (expected {expected})", String::from_utf8_lossy(.tag))]
+    InvalidEntrySize {
+        tag: ChunkTag,
+        offset: u64,
+        expected: u64,
+        found: u64,
+    },
+    #[error("Invalid string in chunk {} at offset {offset}: {source}", String::from_utf8_lossy(.tag))]
+    InvalidString {
+        tag: ChunkTag,
+        offset: u64,
+        source: FromUtf8Error,
+    },
+    #[error("Sample {index} from chunk {} has offset {offset} outside of sample data (size {size})", String::from_utf8_lossy(.tag))]
+    SampleOffsetOutOfRange {
+        tag: ChunkTag,
+        offset: u64,
+        index: usize,
+        size: u64,
+    },
+    #[error("Can't find layout of sample {index} at offset {offset} of sample data with {channels} channels, channel end blocks don't line up")]
+    UnknownSampleLayout {
+        index: usize,
+        offset: u64,
+        channels: usize,
+    },
+    #[error("Failed to encode sample {index} at offset {offset} of sample data to \"{}\": {source}", .path.display())]
+    Encoder {
+        index: usize,
+        offset: u64,
+        path: PathBuf,
+        source: IOError,
+    },
+    #[error("Failed to read midi: {0}")]
+    Midi(String),
+    #[error("Midi is missing \"{name}\" track")]
+    MissingTrack {
+        name: String,
+    },
+    #[error("Failed to read wav: {0}")]
+    Wav(String),
+    #[error("Invalid bank manifest: {0}")]
+    Manifest(String),
+    #[error("Invalid name template \"{template}\": {message}")]
+    NameTemplate {
+        template: String,
+        message: String,
+    },
+    #[error("Unsupported ark version {version}")]
+    UnsupportedArkVersion {
+        version: u32,
+    },
+    #[error("Invalid ark entry {index}: {message}")]
+    InvalidArkEntry {
+        index: usize,
+        message: String,
+    },
+    #[error("Ark has no entry \"{path}\"")]
+    MissingArkEntry {
+        path: String,
+    },
+    #[error("Invalid disc image: {0}")]
+    InvalidIso(String),
+    #[error("Disc image has no file \"{path}\"")]
+    MissingIsoEntry {
+        path: String,
+    },
+}
+
+impl Error {
+    /// Attaches chunk context to errors raised without it
+    pub(crate) fn with_chunk(self, chunk_tag: ChunkTag, chunk_offset: u64, chunk_size: u64, stream_len: u64) -> Self {
+        match self {
+            Error::IO { tag: None, offset: None, source } if source.kind() == ErrorKind::UnexpectedEof => Error::TruncatedChunk {
+                tag: chunk_tag,
+                offset: chunk_offset,
+                expected: chunk_size,
+                found: stream_len.saturating_sub(chunk_offset),
+            },
+            Error::IO { tag: None, offset: None, source } => Error::IO {
+                tag: Some(chunk_tag),
+                offset: Some(chunk_offset),
+                source,
+            },
+            err => err,
+        }
+    }
+}
+
+impl From<IOError> for Error {
+    fn from(source: IOError) -> Self {
+        Error::IO {
+            tag: None,
+            offset: None,
+            source,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Manifest(err.to_string())
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Manifest(err.to_string())
+    }
+}
@@ -0,0 +1,295 @@
+use crate::bank::*;
+use crate::Error;
+use super::*;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+// Articulation connection destinations
+const CONN_DST_PAN: u16 = 0x0004;
+
+/// Level of collection, decides where zone articulation (pan) is written
+///
+/// Articulation is written per region since zones of an inst can be panned differently.
+/// DLS Level 1 only defines articulation per instrument, so Level 1 players ignore region `art1` and play zones centered.
+/// Use Level 2 (region `art2`) to keep pan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DlsLevel {
+    #[default]
+    One,
+    Two,
+}
+
+/// Writes bank to .dls file, reading sample data from .nse stream
+pub fn write_dls_to_file<T: AsRef<Path>, R: Read + Seek>(bank: &BankFile, sample_reader: &mut R, name: &str, level: DlsLevel, path: T) -> Result<(), Error> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_dls(bank, sample_reader, name, level, &mut file)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Writes bank as DLS collection, each inst becomes instrument at bank/program with sdes entries as regions
+pub fn write_dls<T: Write, R: Read + Seek>(bank: &BankFile, sample_reader: &mut R, name: &str, level: DlsLevel, writer: &mut T) -> Result<(), Error> {
+    let samples = (0..bank.samples.len())
+        .map(|i| bank.decode_sample_mono(sample_reader, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Instruments
+    let mut lins = Vec::new();
+
+    for (i, inst) in bank.insts.iter().enumerate() {
+        let bank_num = find_bank_for_inst(bank, i)
+            .map(|b| b.bank_num)
+            .unwrap_or_default();
+
+        let mut lrgn = Vec::new();
+        let mut region_count = 0u32;
+
+        for sdes in bank.inst_sdes_range(i).map(|s| &bank.sdes[s]) {
+            let Some(decoded) = samples.get(sdes.samp as usize) else {
+                continue;
+            };
+
+            let rgn = write_region(sdes, decoded, level);
+            write_list(&mut lrgn, b"rgn ", &rgn);
+            region_count += 1;
+        }
+
+        let mut insh = Vec::new();
+        insh.extend(region_count.to_le_bytes());
+        insh.extend(((bank_num as u32 & 0x7F) << 8).to_le_bytes()); // Bank select MSB
+        insh.extend((inst.prog as u32 & 0x7F).to_le_bytes());
+
+        let mut info = Vec::new();
+        write_chunk(&mut info, b"INAM", &zstr(inst.name.as_bytes()));
+
+        let mut ins = Vec::new();
+        write_chunk(&mut ins, b"insh", &insh);
+        write_list(&mut ins, b"lrgn", &lrgn);
+        write_list(&mut ins, b"INFO", &info);
+
+        write_list(&mut lins, b"ins ", &ins);
+    }
+
+    // Wave pool and offsets to each wave
+    let mut wvpl = Vec::new();
+    let mut ptbl = Vec::new();
+    ptbl.extend(8u32.to_le_bytes());
+    ptbl.extend((samples.len() as u32).to_le_bytes());
+
+    for (entry, decoded) in bank.samples.iter().zip(samples.iter()) {
+        ptbl.extend((wvpl.len() as u32).to_le_bytes());
+
+        let mut fmt = Vec::new();
+        fmt.extend(1u16.to_le_bytes()); // PCM
+        fmt.extend(1u16.to_le_bytes()); // Mono
+        fmt.extend(entry.sample_rate.to_le_bytes());
+        fmt.extend((entry.sample_rate * 2).to_le_bytes());
+        fmt.extend(2u16.to_le_bytes()); // Block align
+        fmt.extend(16u16.to_le_bytes()); // Bits per sample
+
+        let data = decoded.pcm
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let mut info = Vec::new();
+        write_chunk(&mut info, b"INAM", &zstr(entry.name.as_bytes()));
+
+        let mut wave = Vec::new();
+        write_chunk(&mut wave, b"fmt ", &fmt);
+        write_chunk(&mut wave, b"data", &data);
+        write_list(&mut wave, b"INFO", &info);
+
+        write_list(&mut wvpl, b"wave", &wave);
+    }
+
+    let mut info = Vec::new();
+    write_chunk(&mut info, b"INAM", &zstr(name.as_bytes()));
+
+    let mut dls = b"DLS ".to_vec();
+    write_chunk(&mut dls, b"colh", &(bank.insts.len() as u32).to_le_bytes());
+    write_list(&mut dls, b"lins", &lins);
+    write_chunk(&mut dls, b"ptbl", &ptbl);
+    write_list(&mut dls, b"wvpl", &wvpl);
+    write_list(&mut dls, b"INFO", &info);
+
+    let mut riff = Vec::new();
+    write_chunk(&mut riff, b"RIFF", &dls);
+
+    writer.write_all(&riff)?;
+    Ok(())
+}
+
+fn write_region(sdes: &SdesEntry, decoded: &DecodedSample, level: DlsLevel) -> Vec<u8> {
+    let mut rgnh = Vec::new();
+    rgnh.extend((sdes.min_pitch as u16).to_le_bytes());
+    rgnh.extend((sdes.max_pitch as u16).to_le_bytes());
+    rgnh.extend(0u16.to_le_bytes()); // Velocity range
+    rgnh.extend(127u16.to_le_bytes());
+    rgnh.extend(0u16.to_le_bytes()); // Options
+    rgnh.extend(0u16.to_le_bytes()); // Key group
+
+    // Unity note is where sample plays at original pitch, so move it opposite of transpose
+    let unity_note = (sdes.base_pitch as i32 - zone_transpose(sdes)).clamp(0, 127) as u16;
+    let gain = -(volume_to_centibels(sdes.vol) * 65536.) as i32; // 1/655360 dB
+
+    let loop_points = match (decoded.loop_start, decoded.loop_end) {
+        (Some(start), Some(end)) if start < end => Some((start, end - start)),
+        _ => None,
+    };
+
+    let mut wsmp = Vec::new();
+    wsmp.extend(20u32.to_le_bytes());
+    wsmp.extend(unity_note.to_le_bytes());
+    wsmp.extend(0i16.to_le_bytes()); // Fine tune
+    wsmp.extend(gain.to_le_bytes());
+    wsmp.extend(0u32.to_le_bytes()); // Options
+    wsmp.extend((loop_points.is_some() as u32).to_le_bytes());
+
+    if let Some((start, length)) = loop_points {
+        wsmp.extend(16u32.to_le_bytes());
+        wsmp.extend(0u32.to_le_bytes()); // Forward loop
+        wsmp.extend(start.to_le_bytes());
+        wsmp.extend(length.to_le_bytes());
+    }
+
+    let mut wlnk = Vec::new();
+    wlnk.extend(0u16.to_le_bytes()); // Options
+    wlnk.extend(0u16.to_le_bytes()); // Phase group
+    wlnk.extend(1u32.to_le_bytes()); // Left channel
+    wlnk.extend((sdes.samp as u32).to_le_bytes()); // Wave index in ptbl
+
+    let connections = [
+        (CONN_DST_PAN, (sdes.pan_position() * 500.) as i32), // 0.1%
+    ];
+
+    let mut art = Vec::new();
+    art.extend(8u32.to_le_bytes());
+    art.extend((connections.len() as u32).to_le_bytes());
+
+    for (destination, value) in connections {
+        art.extend(0u16.to_le_bytes()); // Source
+        art.extend(0u16.to_le_bytes()); // Control
+        art.extend(destination.to_le_bytes());
+        art.extend(0u16.to_le_bytes()); // Transform
+        art.extend(value.wrapping_mul(65536).to_le_bytes()); // 16.16 fixed point
+    }
+
+    let mut articulation = Vec::new();
+    let mut rgn = Vec::new();
+
+    write_chunk(&mut rgn, b"rgnh", &rgnh);
+    write_chunk(&mut rgn, b"wsmp", &wsmp);
+    write_chunk(&mut rgn, b"wlnk", &wlnk);
+
+    // Region art1 is ignored by Level 1 players, see `DlsLevel`
+    match level {
+        DlsLevel::One => {
+            write_chunk(&mut articulation, b"art1", &art);
+            write_list(&mut rgn, b"lart", &articulation);
+        },
+        DlsLevel::Two => {
+            write_chunk(&mut articulation, b"art2", &art);
+            write_list(&mut rgn, b"lar2", &articulation);
+        }
+    }
+
+    rgn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::*;
+    use std::io::Cursor;
+
+    fn u32_values(data: &[u8]) -> Vec<u32> {
+        data.chunks_exact(4)
+            .map(|v| u32::from_le_bytes(v.try_into().unwrap()))
+            .collect()
+    }
+
+    fn write_test_dls(level: DlsLevel) -> Vec<u8> {
+        let (bank, sample_data) = looped_bank();
+
+        let mut dls = Vec::new();
+        write_dls(&bank, &mut Cursor::new(&sample_data), "test", level, &mut dls).unwrap();
+        dls
+    }
+
+    #[test]
+    fn writes_instrument_regions_and_pool() {
+        let dls = write_test_dls(DlsLevel::One);
+
+        let riff = read_chunks(&dls);
+        assert_eq!(riff[0].0, b"RIFF");
+        assert_eq!(&riff[0].1[..4], b"DLS ");
+
+        let collection = read_chunks(&riff[0].1[4..]);
+        let tags = collection.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(tags, [b"colh", b"LIST", b"ptbl", b"LIST", b"LIST"]);
+        assert_eq!(u32_values(collection[0].1), [1]);
+
+        // Size, cue count and offset of only wave
+        assert_eq!(u32_values(collection[2].1), [8, 1, 0]);
+
+        let lins = read_chunks(list_data(&collection[1], b"lins"));
+        assert_eq!(lins.len(), 1);
+
+        let ins = read_chunks(list_data(&lins[0], b"ins "));
+        assert_eq!(ins[0].0, b"insh");
+        assert_eq!(u32_values(ins[0].1), [1, 1 << 8, 3]); // Regions, bank, program
+
+        let lrgn = read_chunks(list_data(&ins[1], b"lrgn"));
+        assert_eq!(lrgn.len(), 1);
+
+        let rgn = read_chunks(list_data(&lrgn[0], b"rgn "));
+        let tags = rgn.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(tags, [b"rgnh", b"wsmp", b"wlnk", b"LIST"]);
+
+        // Key range 36-72, velocity 0-127
+        assert_eq!(&rgn[0].1[..8], [36, 0, 72, 0, 0, 0, 127, 0]);
+
+        // Unity note moves down by transpose, single forward loop
+        let wsmp = rgn[1].1;
+        assert_eq!(u32_values(&wsmp[..4]), [20]);
+        assert_eq!(u16::from_le_bytes([wsmp[4], wsmp[5]]), 46);
+        assert_eq!(u32_values(&wsmp[8..]), [0, 0, 1, 16, 0, LOOP_START, LOOP_END - LOOP_START]);
+
+        // Options, phase group, channel and wave index
+        assert_eq!(rgn[2].1, [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let lart = read_chunks(list_data(&rgn[3], b"lart"));
+        assert_eq!(lart[0].0, b"art1");
+
+        // Pan fully right
+        let art = u32_values(lart[0].1);
+        assert_eq!(art[..2], [8, 1]);
+        assert_eq!(art[3], CONN_DST_PAN as u32);
+        assert_eq!(art[4] as i32, 500 << 16);
+
+        let wvpl = read_chunks(list_data(&collection[3], b"wvpl"));
+        assert_eq!(wvpl.len(), 1);
+
+        let wave = read_chunks(list_data(&wvpl[0], b"wave"));
+        assert_eq!(wave[0].0, b"fmt ");
+        assert_eq!(wave[1].0, b"data");
+        assert_eq!(wave[1].1.len(), LOOP_END as usize * 2);
+    }
+
+    #[test]
+    fn level_two_writes_art2() {
+        let dls = write_test_dls(DlsLevel::Two);
+        let riff = read_chunks(&dls);
+        let collection = read_chunks(&riff[0].1[4..]);
+
+        let lins = read_chunks(list_data(&collection[1], b"lins"));
+        let ins = read_chunks(list_data(&lins[0], b"ins "));
+        let lrgn = read_chunks(list_data(&ins[1], b"lrgn"));
+        let rgn = read_chunks(list_data(&lrgn[0], b"rgn "));
+
+        let lar2 = read_chunks(list_data(&rgn[3], b"lar2"));
+        assert_eq!(lar2[0].0, b"art2");
+    }
+}
@@ -0,0 +1,172 @@
+//! Instrument exports of a bank
+//!
+//! Each export covers a single bank, bank switches of a song aren't followed.
+//! For songs that switch banks, export each of `BankTimeline::banks` on its own.
+//!
+//! Zones are written without envelopes or fine tune since neither is decoded from SDES (see `SdesEntry`),
+//! players fall back to their default envelope and only the coarse transpose is kept.
+
+pub mod dls;
+pub mod sf2;
+pub mod sfz;
+
+use crate::bank::*;
+
+// Most instrument formats cap attenuation at 144 dB
+const MAX_ATTENUATION_CB: f32 = 1440.0;
+
+/// Attenuation in centibels for zone volume (0-127)
+pub(crate) fn volume_to_centibels(vol: u8) -> f32 {
+    ratio_to_centibels(vol.min(127) as f32 / 127.)
+}
+
+/// Attenuation in centibels for amplitude ratio (0.0-1.0)
+pub(crate) fn ratio_to_centibels(ratio: f32) -> f32 {
+    match ratio {
+        r if r <= 0. => MAX_ATTENUATION_CB,
+        r => (-200. * r.log10()).clamp(0., MAX_ATTENUATION_CB),
+    }
+}
+
+/// Transpose of zone in semitones
+pub(crate) fn zone_transpose(sdes: &SdesEntry) -> i32 {
+    (sdes.transpose as i8) as i32
+}
+
+/// Bank entry that owns inst, if any
+pub(crate) fn find_bank_for_inst(bank: &BankFile, inst_index: usize) -> Option<&BankEntry> {
+    bank.banks
+        .iter()
+        .enumerate()
+        .find(|(i, _)| bank.bank_inst_range(*i).contains(&inst_index))
+        .map(|(_, b)| b)
+}
+
+/// Writes RIFF chunk to buffer
+pub(crate) fn write_chunk(out: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    out.extend(tag);
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+
+    // Chunks are word aligned
+    if (data.len() & 1) != 0 {
+        out.push(0);
+    }
+}
+
+/// Writes RIFF LIST chunk to buffer
+pub(crate) fn write_list(out: &mut Vec<u8>, list_type: &[u8; 4], data: &[u8]) {
+    let mut list = list_type.to_vec();
+    list.extend(data);
+
+    write_chunk(out, b"LIST", &list);
+}
+
+/// Zero terminated string padded to even size
+pub(crate) fn zstr(text: &[u8]) -> Vec<u8> {
+    let mut data = text.to_vec();
+    data.push(0);
+
+    if (data.len() & 1) != 0 {
+        data.push(0);
+    }
+
+    data
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::vag::VAG_SAMPLES_PER_BLOCK;
+    use std::io::Cursor;
+
+    pub(crate) const LOOP_START: u32 = VAG_SAMPLES_PER_BLOCK as u32 * 2;
+    pub(crate) const LOOP_END: u32 = VAG_SAMPLES_PER_BLOCK as u32 * 6;
+
+    // Sample ends at loop end, same as encoded samples
+    fn test_pcm() -> Vec<i16> {
+        (0..LOOP_END)
+            .map(|i| ((i % 32) as i16 - 16) * 512)
+            .collect()
+    }
+
+    // Bank 1 with "lead" inst at program 3, single zone playing looped sample 0 panned right
+    pub(crate) fn looped_bank() -> (BankFile, Vec<u8>) {
+        let (sample_data, _) = encode_sample_data(&test_pcm(), 1, Some((LOOP_START, LOOP_END)));
+        let mut bank = BankFile::default();
+
+        bank.banks.push(BankEntry {
+            bank_num: 1,
+            inst_count: 1,
+            ..Default::default()
+        });
+
+        bank.insts.push(InstEntry {
+            name: String::from("lead"),
+            prog: 3,
+            sdes: 1,
+            ..Default::default()
+        });
+
+        bank.sdes.push(SdesEntry {
+            min_pitch: 36,
+            max_pitch: 72,
+            base_pitch: 48,
+            transpose: 2,
+            vol: 127,
+            pan: 127,
+            samp: 0,
+            ..Default::default()
+        });
+
+        bank.samples.push(SampleEntry {
+            name: String::from("lead_c3"),
+            file_name: String::from("lead_c3.wav"),
+            channels: 1,
+            sample_rate: 22050,
+            ..Default::default()
+        });
+
+        bank.measure_samples(&mut Cursor::new(&sample_data)).unwrap();
+        bank.update_loop_points(&mut Cursor::new(&sample_data)).unwrap();
+
+        (bank, sample_data)
+    }
+
+    // Appends unnamed one-shot sample with same pcm after data of looped_bank, no zone plays it
+    pub(crate) fn push_one_shot_sample(bank: &mut BankFile, sample_data: &mut Vec<u8>) {
+        bank.samples.push(SampleEntry {
+            channels: 1,
+            sample_rate: 22050,
+            pos: sample_data.len() as u32,
+            ..Default::default()
+        });
+
+        sample_data.extend(encode_sample_data(&test_pcm(), 1, None).0);
+        bank.measure_samples(&mut Cursor::new(&sample_data)).unwrap();
+    }
+
+    // Splits RIFF data into (tag, data) chunks, checking sizes end exactly at end of data
+    pub(crate) fn read_chunks(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let size = u32::from_le_bytes(data[(offset + 4)..(offset + 8)].try_into().unwrap()) as usize;
+            chunks.push((&data[offset..(offset + 4)], &data[(offset + 8)..(offset + 8 + size)]));
+
+            offset += 8 + size + (size & 1);
+        }
+
+        assert_eq!(offset, data.len());
+        chunks
+    }
+
+    // Data of LIST chunk after checking its type
+    pub(crate) fn list_data<'a>(chunk: &(&[u8], &'a [u8]), list_type: &[u8; 4]) -> &'a [u8] {
+        assert_eq!(chunk.0, b"LIST");
+        assert_eq!(&chunk.1[..4], list_type);
+
+        &chunk.1[4..]
+    }
+}
@@ -0,0 +1,293 @@
+use crate::bank::*;
+use crate::Error;
+use super::*;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+// Zero samples required after each sample
+const SAMPLE_PADDING: usize = 46;
+const NAME_SIZE: usize = 20;
+
+// Generator operators
+const GEN_PAN: u16 = 17;
+const GEN_INSTRUMENT: u16 = 41;
+const GEN_KEY_RANGE: u16 = 43;
+const GEN_INITIAL_ATTENUATION: u16 = 48;
+const GEN_COARSE_TUNE: u16 = 51;
+const GEN_SAMPLE_ID: u16 = 53;
+const GEN_SAMPLE_MODES: u16 = 54;
+const GEN_OVERRIDING_ROOT_KEY: u16 = 58;
+
+/// Writes bank to .sf2 file, reading sample data from .nse stream
+pub fn write_sf2_to_file<T: AsRef<Path>, R: Read + Seek>(bank: &BankFile, sample_reader: &mut R, name: &str, path: T) -> Result<(), Error> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_sf2(bank, sample_reader, name, &mut file)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// Writes bank as SoundFont 2, each inst becomes preset at bank/program with sdes entries as zones
+///
+/// SoundFont samples are written mono, multi-channel samples are mixed down (see `BankFile::decode_sample_mono`)
+pub fn write_sf2<T: Write, R: Read + Seek>(bank: &BankFile, sample_reader: &mut R, name: &str, writer: &mut T) -> Result<(), Error> {
+    let samples = (0..bank.samples.len())
+        .map(|i| bank.decode_sample_mono(sample_reader, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut info = Vec::new();
+    write_chunk(&mut info, b"ifil", &[2, 0, 1, 0]); // Version 2.01
+    write_chunk(&mut info, b"isng", &zstr(b"EMU8000"));
+    write_chunk(&mut info, b"INAM", &zstr(name.as_bytes()));
+
+    // Sample data and headers
+    let mut smpl = Vec::new();
+    let mut shdr = Vec::new();
+
+    for (entry, decoded) in bank.samples.iter().zip(samples.iter()) {
+        let start = (smpl.len() / 2) as u32;
+        let end = start + decoded.pcm.len() as u32;
+
+        smpl.extend(decoded.pcm.iter().flat_map(|s| s.to_le_bytes()));
+        smpl.extend([0u8; SAMPLE_PADDING * 2]);
+
+        let (loop_start, loop_end) = match (decoded.loop_start, decoded.loop_end) {
+            (Some(s), Some(e)) => (start + s, start + e),
+            _ => (start, end),
+        };
+
+        shdr.extend(fixed_name(&entry.name));
+        shdr.extend(start.to_le_bytes());
+        shdr.extend(end.to_le_bytes());
+        shdr.extend(loop_start.to_le_bytes());
+        shdr.extend(loop_end.to_le_bytes());
+        shdr.extend(entry.sample_rate.to_le_bytes());
+        shdr.push(60); // Original pitch, root key set per zone
+        shdr.push(0); // Pitch correction
+        shdr.extend(0u16.to_le_bytes()); // Sample link
+        shdr.extend(1u16.to_le_bytes()); // Mono sample
+    }
+
+    shdr.extend(fixed_name("EOS"));
+    shdr.extend([0u8; 26]);
+
+    // Instruments, one per inst entry
+    let mut inst = Vec::new();
+    let mut ibag = Vec::new();
+    let mut igen = Vec::new();
+
+    for (i, inst_entry) in bank.insts.iter().enumerate() {
+        inst.extend(fixed_name(&inst_entry.name));
+        inst.extend(((ibag.len() / 4) as u16).to_le_bytes());
+
+        for sdes in bank.inst_sdes_range(i).map(|s| &bank.sdes[s]) {
+            let Some(decoded) = samples.get(sdes.samp as usize) else {
+                continue;
+            };
+
+            write_bag(&mut ibag, &igen);
+            write_zone_generators(&mut igen, sdes, decoded);
+        }
+    }
+
+    inst.extend(fixed_name("EOI"));
+    inst.extend(((ibag.len() / 4) as u16).to_le_bytes());
+    write_bag(&mut ibag, &igen);
+    igen.extend([0u8; 4]);
+
+    // Presets, one per inst at bank number and program
+    let mut presets = bank.insts
+        .iter()
+        .enumerate()
+        .map(|(i, inst_entry)| {
+            let bank_num = find_bank_for_inst(bank, i)
+                .map(|b| b.bank_num)
+                .unwrap_or_default();
+
+            (bank_num as u16, inst_entry.prog, i)
+        })
+        .collect::<Vec<_>>();
+
+    // Presets should be sorted by bank and program
+    presets.sort();
+
+    let mut phdr = Vec::new();
+    let mut pbag = Vec::new();
+    let mut pgen = Vec::new();
+
+    for (bank_num, prog, inst_index) in presets {
+        phdr.extend(fixed_name(&bank.insts[inst_index].name));
+        phdr.extend(prog.to_le_bytes());
+        phdr.extend(bank_num.to_le_bytes());
+        phdr.extend(((pbag.len() / 4) as u16).to_le_bytes());
+        phdr.extend([0u8; 12]); // Library, genre, morphology
+
+        write_bag(&mut pbag, &pgen);
+        write_gen(&mut pgen, GEN_INSTRUMENT, inst_index as u16);
+    }
+
+    phdr.extend(fixed_name("EOP"));
+    phdr.extend([0u8; 4]);
+    phdr.extend(((pbag.len() / 4) as u16).to_le_bytes());
+    phdr.extend([0u8; 12]);
+    write_bag(&mut pbag, &pgen);
+    pgen.extend([0u8; 4]);
+
+    let mut sdta = Vec::new();
+    write_chunk(&mut sdta, b"smpl", &smpl);
+
+    let mut pdta = Vec::new();
+    write_chunk(&mut pdta, b"phdr", &phdr);
+    write_chunk(&mut pdta, b"pbag", &pbag);
+    write_chunk(&mut pdta, b"pmod", &[0u8; 10]);
+    write_chunk(&mut pdta, b"pgen", &pgen);
+    write_chunk(&mut pdta, b"inst", &inst);
+    write_chunk(&mut pdta, b"ibag", &ibag);
+    write_chunk(&mut pdta, b"imod", &[0u8; 10]);
+    write_chunk(&mut pdta, b"igen", &igen);
+    write_chunk(&mut pdta, b"shdr", &shdr);
+
+    let mut sfbk = b"sfbk".to_vec();
+    write_list(&mut sfbk, b"INFO", &info);
+    write_list(&mut sfbk, b"sdta", &sdta);
+    write_list(&mut sfbk, b"pdta", &pdta);
+
+    let mut riff = Vec::new();
+    write_chunk(&mut riff, b"RIFF", &sfbk);
+
+    writer.write_all(&riff)?;
+    Ok(())
+}
+
+fn write_zone_generators(igen: &mut Vec<u8>, sdes: &SdesEntry, decoded: &DecodedSample) {
+    // Key range must be first
+    write_gen(igen, GEN_KEY_RANGE, u16::from_le_bytes([sdes.min_pitch, sdes.max_pitch]));
+
+    write_gen(igen, GEN_COARSE_TUNE, zone_transpose(sdes) as i16 as u16);
+
+    write_gen(igen, GEN_INITIAL_ATTENUATION, volume_to_centibels(sdes.vol).round() as u16);
+    write_gen(igen, GEN_PAN, (sdes.pan_position() * 500.).round() as i16 as u16);
+
+    if decoded.loop_end.is_some() {
+        write_gen(igen, GEN_SAMPLE_MODES, 1); // Continuous loop
+    }
+
+    write_gen(igen, GEN_OVERRIDING_ROOT_KEY, sdes.base_pitch as u16);
+
+    // Sample id must be last
+    write_gen(igen, GEN_SAMPLE_ID, sdes.samp as u16);
+}
+
+fn write_bag(bag: &mut Vec<u8>, gens: &[u8]) {
+    bag.extend(((gens.len() / 4) as u16).to_le_bytes());
+    bag.extend(0u16.to_le_bytes()); // Modulator index, none used
+}
+
+fn write_gen(gens: &mut Vec<u8>, oper: u16, amount: u16) {
+    gens.extend(oper.to_le_bytes());
+    gens.extend(amount.to_le_bytes());
+}
+
+fn fixed_name(name: &str) -> [u8; NAME_SIZE] {
+    let mut data = [0u8; NAME_SIZE];
+
+    for (d, c) in data.iter_mut().zip(name.bytes().filter(|c| c.is_ascii()).take(NAME_SIZE - 1)) {
+        *d = c;
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::*;
+    use std::io::Cursor;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..(offset + 4)].try_into().unwrap())
+    }
+
+    fn name_at(data: &[u8], offset: usize) -> &[u8] {
+        let name = &data[offset..(offset + NAME_SIZE)];
+        let len = name.iter().position(|c| *c == 0).unwrap_or(NAME_SIZE);
+
+        &name[..len]
+    }
+
+    #[test]
+    fn writes_looped_zone_structure() {
+        let (bank, sample_data) = looped_bank();
+
+        let mut sf2 = Vec::new();
+        write_sf2(&bank, &mut Cursor::new(&sample_data), "test", &mut sf2).unwrap();
+
+        let riff = read_chunks(&sf2);
+        assert_eq!(riff.len(), 1);
+        assert_eq!(riff[0].0, b"RIFF");
+        assert_eq!(&riff[0].1[..4], b"sfbk");
+
+        let lists = read_chunks(&riff[0].1[4..]);
+        assert_eq!(lists.len(), 3);
+
+        let info = read_chunks(list_data(&lists[0], b"INFO"));
+        assert_eq!(info[0], (b"ifil".as_slice(), [2, 0, 1, 0].as_slice()));
+
+        let sdta = read_chunks(list_data(&lists[1], b"sdta"));
+        assert_eq!(sdta[0].0, b"smpl");
+        assert_eq!(sdta[0].1.len(), (LOOP_END as usize + SAMPLE_PADDING) * 2);
+
+        let pdta = read_chunks(list_data(&lists[2], b"pdta"));
+        let tags = pdta.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(tags, [b"phdr", b"pbag", b"pmod", b"pgen", b"inst", b"ibag", b"imod", b"igen", b"shdr"]);
+
+        // Records per chunk, each with terminal record
+        let sizes = [38, 4, 10, 4, 22, 4, 10, 4, 46];
+        let counts = pdta
+            .iter()
+            .zip(sizes)
+            .map(|((_, data), size)| {
+                assert_eq!(data.len() % size, 0);
+                data.len() / size
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(counts, [2, 2, 1, 2, 2, 2, 1, 8, 2]);
+
+        let (phdr, inst, igen, shdr) = (pdta[0].1, pdta[4].1, pdta[7].1, pdta[8].1);
+
+        assert_eq!(name_at(phdr, 0), b"lead");
+        assert_eq!(u16_at(phdr, 20), 3); // Program
+        assert_eq!(u16_at(phdr, 22), 1); // Bank
+        assert_eq!(name_at(phdr, 38), b"EOP");
+        assert_eq!(u16_at(pdta[3].1, 0), GEN_INSTRUMENT);
+        assert_eq!(name_at(inst, 0), b"lead");
+        assert_eq!(name_at(inst, 22), b"EOI");
+        assert_eq!(name_at(shdr, 0), b"lead_c3");
+        assert_eq!(name_at(shdr, 46), b"EOS");
+
+        // Start, end, loop start and loop end
+        let points = (0..4).map(|i| u32_at(shdr, NAME_SIZE + i * 4)).collect::<Vec<_>>();
+        assert_eq!(points, [0, LOOP_END, LOOP_START, LOOP_END]);
+
+        let gens = igen
+            .chunks_exact(4)
+            .map(|g| (u16_at(g, 0), u16_at(g, 2)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(gens, [
+            (GEN_KEY_RANGE, u16::from_le_bytes([36, 72])),
+            (GEN_COARSE_TUNE, 2),
+            (GEN_INITIAL_ATTENUATION, 0),
+            (GEN_PAN, 500),
+            (GEN_SAMPLE_MODES, 1),
+            (GEN_OVERRIDING_ROOT_KEY, 48),
+            (GEN_SAMPLE_ID, 0),
+            (0, 0),
+        ]);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::bank::*;
+use crate::naming::*;
+use crate::{create_output_dir, Error};
+use super::*;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes .sfz for each inst to directory with extracted samples, returns number of files written
+///
+/// Sample paths follow same naming policy used to extract samples.
+/// Loop opcodes come from sample loop points, filled in by `BankFile::update_loop_points` or when extracting samples
+pub fn write_sfz_to_dir<T: AsRef<Path>>(bank: &BankFile, naming: &SampleNaming, output_dir_path: T) -> Result<usize, Error> {
+    let output_dir = output_dir_path.as_ref();
+
+    create_output_dir(output_dir)?;
+
+    let sample_file_names = naming.file_names(bank);
+    let mut unique_names = UniqueNames::new();
+
+    for (i, inst) in bank.insts.iter().enumerate() {
+        let file_name = match sanitize_file_name(&inst.name) {
+            name if name.is_empty() => format!("inst_{i}"),
+            name => name,
+        };
+
+        let file_name = unique_names.insert(file_name);
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(output_dir.join(format!("{file_name}.sfz")))?);
+        write_sfz(bank, i, &sample_file_names, &mut file)?;
+        file.flush()?;
+    }
+
+    Ok(bank.insts.len())
+}
+
+/// Writes inst as .sfz with region for each sdes entry, samples referenced relative to .sfz
+///
+/// Sample file names are indexed by sample, see `SampleNaming::file_names`
+pub fn write_sfz<T: Write>(bank: &BankFile, inst_index: usize, sample_file_names: &[String], writer: &mut T) -> Result<(), Error> {
+    if let Some(inst) = bank.insts.get(inst_index) {
+        let bank_num = find_bank_for_inst(bank, inst_index)
+            .map(|b| b.bank_num)
+            .unwrap_or_default();
+
+        writeln!(writer, "// {} (bank {}, program {})", inst.name, bank_num, inst.prog)?;
+    }
+
+    for sdes in bank.inst_sdes_range(inst_index).map(|i| &bank.sdes[i]) {
+        let (Some(sample), Some(sample_file_name)) = (bank.samples.get(sdes.samp as usize), sample_file_names.get(sdes.samp as usize)) else {
+            continue;
+        };
+
+        writeln!(writer)?;
+        writeln!(writer, "<region>")?;
+        writeln!(writer, "sample={}", sample_file_name)?;
+        writeln!(writer, "lokey={} hikey={} pitch_keycenter={}", sdes.min_pitch, sdes.max_pitch, sdes.base_pitch)?;
+        writeln!(writer, "transpose={}", zone_transpose(sdes))?;
+        writeln!(writer, "volume={:.2} pan={:.1}", -volume_to_centibels(sdes.vol) / 10., sdes.pan_position() * 100.)?;
+
+        match (sample.loop_start, sample.loop_end) {
+            (Some(start), Some(end)) if start < end => {
+                writeln!(writer, "loop_mode=loop_continuous loop_start={} loop_end={}", start, end - 1)?;
+            },
+            _ => {
+                writeln!(writer, "loop_mode=no_loop")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::*;
+
+    fn write_inst(bank: &BankFile) -> String {
+        let mut sfz = Vec::new();
+        write_sfz(bank, 0, &[String::from("samples/lead_c3.wav")], &mut sfz).unwrap();
+
+        String::from_utf8(sfz).unwrap()
+    }
+
+    #[test]
+    fn writes_region_opcodes() {
+        let (mut bank, _) = looped_bank();
+        bank.sdes[0].vol = 100;
+
+        let sfz = write_inst(&bank);
+
+        assert_eq!(sfz.lines().collect::<Vec<_>>(), [
+            "// lead (bank 1, program 3)",
+            "",
+            "<region>",
+            "sample=samples/lead_c3.wav",
+            "lokey=36 hikey=72 pitch_keycenter=48",
+            "transpose=2",
+            "volume=-2.08 pan=100.0",
+            &format!("loop_mode=loop_continuous loop_start={LOOP_START} loop_end={}", LOOP_END - 1),
+        ]);
+
+        // One-shot once loop points are gone
+        bank.samples[0].loop_start = None;
+        bank.samples[0].loop_end = None;
+
+        assert_eq!(write_inst(&bank).lines().last(), Some("loop_mode=no_loop"));
+    }
+}
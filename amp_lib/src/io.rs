@@ -1,20 +1,20 @@
-use std::fs::File;
-use std::io::{Error as IOError, Read, Seek};
+use std::io::{Error as IOError, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 pub (crate) trait SimpleReader: Read + Seek {
-    fn read_i8(&mut self) -> Result<i8, IOError>;
     fn read_u8(&mut self) -> Result<u8, IOError>;
     fn read_u16(&mut self) -> Result<u16, IOError>;
     fn read_u32(&mut self) -> Result<u32, IOError>;
     fn read_bytes<const N: usize>(&mut self, b: &mut [u8; N]) -> Result<(), IOError>;
-    fn read_string(&mut self) -> Result<String, IOError>;
-}
+    fn read_string_bytes(&mut self) -> Result<Vec<u8>, IOError>;
 
-impl SimpleReader for File {
-    fn read_i8(&mut self) -> Result<i8, IOError> {
-        read_i8(self)
-    }
+    /// Bytes left after current position
+    ///
+    /// Sizes read from files can be corrupt, so check them against this before allocating
+    fn remaining_len(&mut self) -> Result<u64, IOError>;
+}
 
+impl<T: Read + Seek> SimpleReader for T {
     fn read_u8(&mut self) -> Result<u8, IOError> {
         read_u8(self)
     }
@@ -31,16 +31,13 @@ impl SimpleReader for File {
         read_bytes(self, b)
     }
 
-    fn read_string(&mut self) -> Result<String, IOError> {
-        read_string(self)
+    fn read_string_bytes(&mut self) -> Result<Vec<u8>, IOError> {
+        read_string_bytes(self)
     }
-}
 
-fn read_i8<T: Read + Seek>(reader: &mut T)-> Result<i8, IOError> {
-    let mut b = [0u8; std::mem::size_of::<i8>()];
-    read_bytes(reader, &mut b)?;
-
-    Ok(i8::from_le_bytes(b))
+    fn remaining_len(&mut self) -> Result<u64, IOError> {
+        remaining_len(self)
+    }
 }
 
 fn read_u8<T: Read + Seek>(reader: &mut T)-> Result<u8, IOError> {
@@ -68,12 +65,161 @@ fn read_bytes<const N: usize, T: Read + Seek>(reader: &mut T, b: &mut [u8; N]) -
     reader.read_exact(b)
 }
 
-fn read_string<T: Read + Seek>(reader: &mut T)-> Result<String, IOError> {
+fn read_string_bytes<T: Read + Seek>(reader: &mut T)-> Result<Vec<u8>, IOError> {
     let size = read_u32(reader)?;
+
+    if size as u64 > remaining_len(reader)? {
+        return Err(IOError::new(ErrorKind::UnexpectedEof, format!("String data of {size} bytes is past end of stream")));
+    }
+
     let mut data = vec![0u8; size as usize];
 
     reader.read_exact(&mut data)?;
 
-    // TODO: Properly map error
-    Ok(String::from_utf8(data).unwrap())
-}
\ No newline at end of file
+    Ok(data)
+}
+
+fn remaining_len<T: Read + Seek>(reader: &mut T)-> Result<u64, IOError> {
+    let pos = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(pos))?;
+
+    Ok(len.saturating_sub(pos))
+}
+
+pub (crate) trait SimpleWriter: Write + Seek {
+    fn write_u8(&mut self, v: u8) -> Result<(), IOError>;
+    fn write_u16(&mut self, v: u16) -> Result<(), IOError>;
+    fn write_u32(&mut self, v: u32) -> Result<(), IOError>;
+    fn write_bytes<const N: usize>(&mut self, b: &[u8; N]) -> Result<(), IOError>;
+    fn write_string_bytes(&mut self, b: &[u8]) -> Result<(), IOError>;
+}
+
+impl<T: Write + Seek> SimpleWriter for T {
+    fn write_u8(&mut self, v: u8) -> Result<(), IOError> {
+        self.write_all(&v.to_le_bytes())
+    }
+
+    fn write_u16(&mut self, v: u16) -> Result<(), IOError> {
+        self.write_all(&v.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, v: u32) -> Result<(), IOError> {
+        self.write_all(&v.to_le_bytes())
+    }
+
+    fn write_bytes<const N: usize>(&mut self, b: &[u8; N]) -> Result<(), IOError> {
+        self.write_all(b)
+    }
+
+    fn write_string_bytes(&mut self, b: &[u8]) -> Result<(), IOError> {
+        self.write_u32(b.len() as u32)?;
+        self.write_all(b)
+    }
+}
+
+/// Creates output directory along with any missing parents
+pub fn create_output_dir<T: AsRef<Path>>(path: T) -> Result<(), IOError> {
+    match path.as_ref() {
+        dir if dir.exists() => Ok(()),
+        dir => std::fs::create_dir_all(dir),
+    }
+}
+
+/// Creates parent directory of output file path if missing, relative paths without directory are left alone
+pub fn create_parent_dir<T: AsRef<Path>>(path: T) -> Result<(), IOError> {
+    match path.as_ref().parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => create_output_dir(dir),
+        None => Ok(()),
+    }
+}
+
+/// Streams part of another stream (i.e. entry in .ark part or disc image), offsets are relative to start of part
+pub struct SubStreamReader<T: Read + Seek> {
+    reader: T,
+    start: u64,
+    size: u64,
+    pos: u64,
+}
+
+impl<T: Read + Seek> SubStreamReader<T> {
+    pub fn new(mut reader: T, start: u64, size: u64) -> Result<Self, IOError> {
+        reader.seek(SeekFrom::Start(start))?;
+
+        Ok(Self {
+            reader,
+            start,
+            size,
+            pos: 0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl<T: Read + Seek> Read for SubStreamReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        let remaining = self.size.saturating_sub(self.pos);
+        let max_len = buf.len().min(remaining as usize);
+
+        if max_len == 0 {
+            return Ok(0);
+        }
+
+        let count = self.reader.read(&mut buf[..max_len])?;
+        self.pos += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl<T: Read + Seek> Seek for SubStreamReader<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(p) => self.size.checked_add_signed(p),
+            SeekFrom::Current(p) => self.pos.checked_add_signed(p),
+        };
+
+        let Some(new_pos) = new_pos else {
+            return Err(IOError::new(ErrorKind::InvalidInput, "Seek before start of sub stream"));
+        };
+
+        self.reader.seek(SeekFrom::Start(self.start + new_pos))?;
+        self.pos = new_pos;
+
+        Ok(new_pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sub_stream_reads_only_its_part() {
+        let data = (0u8..32).collect::<Vec<_>>();
+        let mut reader = SubStreamReader::new(Cursor::new(data), 8, 10).unwrap();
+
+        let mut part = Vec::new();
+        reader.read_to_end(&mut part).unwrap();
+        assert_eq!(part, (8u8..18).collect::<Vec<_>>());
+
+        assert_eq!(reader.seek(SeekFrom::End(-2)).unwrap(), 8);
+        assert_eq!(reader.read_u8().unwrap(), 16);
+        assert_eq!(reader.seek(SeekFrom::Current(-3)).unwrap(), 6);
+        assert_eq!(reader.read_u8().unwrap(), 14);
+
+        assert_eq!(reader.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_eq!(reader.remaining_len().unwrap(), 10);
+
+        assert!(reader.seek(SeekFrom::Current(-1)).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{Error, SimpleReader, SubStreamReader};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const ISO_SECTOR_SIZE: u64 = 0x800;
+const ISO_FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+const ISO_FLAG_DIRECTORY: u8 = 0x02;
+
+/// File in disc image, offset is from start of image
+#[derive(Clone, Debug, Default)]
+pub struct IsoEntry {
+    pub path: String, // Without version suffix (i.e. "GEN/MAIN.HDR")
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Minimal ISO9660 reader, only lists files so they can be streamed out of image
+///
+/// Reads directory tree from primary volume descriptor. Extended attributes, Joliet names
+/// and multi-extent files aren't supported (PS2 discs don't use them).
+#[derive(Debug, Default)]
+pub struct IsoImage {
+    pub path: PathBuf,
+    pub entries: Vec<IsoEntry>,
+}
+
+impl IsoImage {
+    pub fn from_path<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut iso = Self::from_reader(&mut reader)?;
+        iso.path = path.to_path_buf();
+
+        Ok(iso)
+    }
+
+    /// Reads file table from image, path is left empty
+    pub fn from_reader<T: Read + Seek>(reader: &mut T) -> Result<Self, Error> {
+        let mut sector = ISO_FIRST_DESCRIPTOR_SECTOR;
+        let mut descriptor = [0u8; ISO_SECTOR_SIZE as usize];
+
+        // Find primary volume descriptor, set ends with type 255
+        loop {
+            reader.seek(SeekFrom::Start(sector * ISO_SECTOR_SIZE))?;
+            reader.read_exact(&mut descriptor)?;
+
+            if !descriptor[1..6].eq(b"CD001") {
+                return Err(Error::InvalidIso(format!("Missing volume descriptor at sector {sector}")));
+            }
+
+            match descriptor[0] {
+                1 => break,
+                255 => return Err(Error::InvalidIso("Missing primary volume descriptor".into())),
+                _ => sector += 1,
+            }
+        }
+
+        let block_size = u16::from_le_bytes([descriptor[128], descriptor[129]]) as u64;
+
+        if block_size == 0 {
+            return Err(Error::InvalidIso("Logical block size is 0".into()));
+        }
+
+        let root = read_dir_record(&descriptor[156..190])
+            .ok_or_else(|| Error::InvalidIso("Invalid root directory record".into()))?;
+
+        let mut entries = Vec::new();
+        let mut visited = HashSet::new();
+
+        // Directories left to read as (path, block, size)
+        let mut dirs = vec![(String::new(), root.block, root.size)];
+
+        while let Some((dir_path, block, size)) = dirs.pop() {
+            if !visited.insert(block) {
+                continue;
+            }
+
+            reader.seek(SeekFrom::Start(block as u64 * block_size))?;
+
+            if size as u64 > reader.remaining_len()? {
+                return Err(Error::InvalidIso(format!("Directory \"{dir_path}\" at block {block} (size {size}) is outside of image")));
+            }
+
+            let mut dir_data = vec![0u8; size as usize];
+            reader.read_exact(&mut dir_data)?;
+
+            let mut pos = 0;
+
+            while pos < dir_data.len() {
+                let record_len = dir_data[pos] as usize;
+
+                // Records don't cross logical blocks, rest of block is zero
+                if record_len == 0 {
+                    pos = (pos / block_size as usize + 1) * block_size as usize;
+                    continue;
+                }
+
+                let Some(record) = dir_data.get(pos..(pos + record_len)).and_then(read_dir_record) else {
+                    return Err(Error::InvalidIso(format!("Invalid directory record in \"{dir_path}\"")));
+                };
+
+                pos += record_len;
+
+                // Skips current and parent dirs
+                if record.name.is_empty() {
+                    continue;
+                }
+
+                let path = match dir_path.is_empty() {
+                    true => record.name,
+                    false => format!("{dir_path}/{}", record.name),
+                };
+
+                if (record.flags & ISO_FLAG_DIRECTORY) != 0 {
+                    dirs.push((path, record.block, record.size));
+                } else {
+                    entries.push(IsoEntry {
+                        path,
+                        offset: record.block as u64 * block_size,
+                        size: record.size as u64,
+                    });
+                }
+            }
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self {
+            path: PathBuf::new(),
+            entries,
+        })
+    }
+
+    /// Finds file by path, ignoring case and slash direction
+    pub fn find_entry(&self, path: &str) -> Option<&IsoEntry> {
+        let path = path.replace('\\', "/");
+        let path = path.trim_start_matches('/');
+
+        self.entries
+            .iter()
+            .find(|e| e.path.eq_ignore_ascii_case(path))
+    }
+
+    /// Opens file data as its own stream
+    pub fn open_entry(&self, entry: &IsoEntry) -> Result<SubStreamReader<BufReader<File>>, Error> {
+        Ok(SubStreamReader::new(BufReader::new(File::open(&self.path)?), entry.offset, entry.size)?)
+    }
+
+    /// Archive headers (.hdr) in image
+    pub fn find_ark_headers(&self) -> Vec<&IsoEntry> {
+        self.entries
+            .iter()
+            .filter(|e| Path::new(&e.path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case("hdr")))
+            .collect()
+    }
+}
+
+struct DirRecord {
+    block: u32,
+    size: u32,
+    flags: u8,
+    name: String, // Empty for current and parent dirs
+}
+
+fn read_dir_record(data: &[u8]) -> Option<DirRecord> {
+    if data.len() < 33 {
+        return None;
+    }
+
+    // Numbers are stored little endian then big endian
+    let block = u32::from_le_bytes(data[2..6].try_into().ok()?);
+    let size = u32::from_le_bytes(data[10..14].try_into().ok()?);
+    let flags = data[25];
+
+    let name_len = data[32] as usize;
+    let name_data = data.get(33..(33 + name_len))?;
+
+    let name = match name_data {
+        [0] | [1] => String::new(),
+        _ => {
+            // Removes version (i.e. ";1") and trailing dot of files without extension
+            let name = String::from_utf8_lossy(name_data);
+            let name = name.split(';').next().unwrap_or_default();
+
+            name.trim_end_matches('.').to_owned()
+        }
+    };
+
+    Some(DirRecord {
+        block,
+        size,
+        flags,
+        name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn dir_record(block: u32, size: u32, flags: u8, name: &[u8]) -> Vec<u8> {
+        let len = 33 + name.len() + ((name.len() + 1) & 1);
+        let mut record = vec![0u8; len];
+
+        record[0] = len as u8;
+        record[2..6].copy_from_slice(&block.to_le_bytes());
+        record[6..10].copy_from_slice(&block.to_be_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[14..18].copy_from_slice(&size.to_be_bytes());
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record[33..(33 + name.len())].copy_from_slice(name);
+
+        record
+    }
+
+    // Image with "GEN/MAIN.HDR" (root dir at block 18, GEN at 19, file at 20)
+    fn test_image(root_size: u32) -> Vec<u8> {
+        let sector = ISO_SECTOR_SIZE as usize;
+        let mut image = vec![0u8; sector * 21];
+
+        let pvd = &mut image[(sector * 16)..(sector * 17)];
+        pvd[0] = 1;
+        pvd[1..6].copy_from_slice(b"CD001");
+        pvd[128..130].copy_from_slice(&(ISO_SECTOR_SIZE as u16).to_le_bytes());
+        pvd[156..190].copy_from_slice(&dir_record(18, root_size, ISO_FLAG_DIRECTORY, &[0]));
+
+        let terminator = &mut image[(sector * 17)..(sector * 18)];
+        terminator[0] = 255;
+        terminator[1..6].copy_from_slice(b"CD001");
+
+        let root = [
+            dir_record(18, sector as u32, ISO_FLAG_DIRECTORY, &[0]),
+            dir_record(18, sector as u32, ISO_FLAG_DIRECTORY, &[1]),
+            dir_record(19, sector as u32, ISO_FLAG_DIRECTORY, b"GEN"),
+        ].concat();
+        image[(sector * 18)..(sector * 18 + root.len())].copy_from_slice(&root);
+
+        let gen = [
+            dir_record(19, sector as u32, ISO_FLAG_DIRECTORY, &[0]),
+            dir_record(18, sector as u32, ISO_FLAG_DIRECTORY, &[1]),
+            dir_record(20, 5, 0, b"MAIN.HDR;1"),
+        ].concat();
+        image[(sector * 19)..(sector * 19 + gen.len())].copy_from_slice(&gen);
+
+        image
+    }
+
+    #[test]
+    fn reads_files_in_directories() {
+        let iso = IsoImage::from_reader(&mut Cursor::new(test_image(ISO_SECTOR_SIZE as u32))).unwrap();
+
+        let entries = iso.entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.offset, e.size))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, [("GEN/MAIN.HDR", 20 * ISO_SECTOR_SIZE, 5)]);
+        assert_eq!(iso.find_ark_headers().len(), 1);
+        assert!(iso.find_entry("\\gen\\main.hdr").is_some());
+    }
+
+    #[test]
+    fn directory_size_past_image_errors() {
+        let err = IsoImage::from_reader(&mut Cursor::new(test_image(u32::MAX))).unwrap_err();
+
+        assert!(matches!(err, Error::InvalidIso(_)), "{err:?}");
+    }
+}
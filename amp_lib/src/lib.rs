@@ -1,4 +1,19 @@
+pub mod ark;
 pub mod bank;
+pub mod builder;
+mod error;
+pub mod export;
 mod io;
+pub mod iso;
+pub mod naming;
+pub mod render;
+pub mod song;
+pub mod stream;
+pub mod timeline;
+pub mod vag;
+pub mod wav;
+pub mod zone;
 
-pub(crate) use io::*;
\ No newline at end of file
+pub use error::*;
+pub(crate) use io::*;
+pub use io::{create_output_dir, create_parent_dir, SubStreamReader};
\ No newline at end of file
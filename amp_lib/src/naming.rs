@@ -0,0 +1,310 @@
+use crate::bank::*;
+use crate::Error;
+use std::collections::HashSet;
+use std::path::Path;
+
+pub const DEFAULT_SAMPLE_NAME_TEMPLATE: &str = "{name}";
+
+// Reserved device names on Windows, can't be used as file names with any extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes name safe to use as single file name, returns empty string if nothing usable is left
+pub fn sanitize_file_name(name: &str) -> String {
+    let mut file_name = name
+        .replace(|c: char| c.is_control() || "/\\:*?\"<>|".contains(c), "_")
+        .trim()
+        .trim_end_matches('.')
+        .to_owned();
+
+    // Only dots left (i.e. "..")
+    if file_name.chars().all(|c| c == '.') {
+        file_name.clear();
+    }
+
+    // Reserved names are matched before first dot (i.e. "con.wav")
+    let stem = file_name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end();
+
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        file_name.insert(stem.len(), '_');
+    }
+
+    file_name
+}
+
+/// Keeps file names unique within directory, ignoring case since not all file systems are case sensitive
+#[derive(Debug, Default)]
+pub struct UniqueNames {
+    used_names: HashSet<String>,
+}
+
+impl UniqueNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns name as-is if unused, otherwise with lowest free number suffix (i.e. "kick_1")
+    pub fn insert(&mut self, name: String) -> String {
+        if self.used_names.insert(name.to_lowercase()) {
+            return name;
+        }
+
+        let mut i = 1;
+
+        loop {
+            let numbered_name = format!("{name}_{i}");
+
+            if self.used_names.insert(numbered_name.to_lowercase()) {
+                return numbered_name;
+            }
+
+            i += 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+enum TemplatePart {
+    Text(String),
+    Index { width: usize, zero_pad: bool },
+    Name,
+    FileName,
+}
+
+/// File naming policy for extracted samples, built from template
+///
+/// Template fields are `{index}`, `{name}` and `{file_name}` (source file name without extension).
+/// Index takes optional width like `{index:03}`, use `{{` and `}}` for literal braces.
+/// Empty names fall back to file name then index, and names are de-duplicated.
+#[derive(Debug)]
+pub struct SampleNaming {
+    parts: Vec<TemplatePart>,
+}
+
+impl Default for SampleNaming {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_NAME_TEMPLATE).unwrap()
+    }
+}
+
+impl SampleNaming {
+    pub fn new(template: &str) -> Result<Self, Error> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut field = String::new();
+                    let mut closed = false;
+
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+
+                        field.push(c);
+                    }
+
+                    if !closed {
+                        return Err(template_error(template, "Unmatched '{'"));
+                    }
+
+                    if !text.is_empty() {
+                        parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                    }
+
+                    parts.push(parse_field(template, &field)?);
+                },
+                '}' => return Err(template_error(template, "Unmatched '}'")),
+                _ => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(TemplatePart::Text(text));
+        }
+
+        Ok(Self {
+            parts,
+        })
+    }
+
+    /// Gets .wav file name for each sample in bank, all unique and safe to write in output directory
+    pub fn file_names(&self, bank: &BankFile) -> Vec<String> {
+        let mut unique_names = UniqueNames::new();
+
+        bank.samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| {
+                let file_name = match sanitize_file_name(&self.format(i, sample)) {
+                    name if name.is_empty() => i.to_string(),
+                    name => name,
+                };
+
+                format!("{}.wav", unique_names.insert(file_name))
+            })
+            .collect()
+    }
+
+    fn format(&self, index: usize, sample: &SampleEntry) -> String {
+        let source_name = Path::new(&sample.file_name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(sanitize_file_name)
+            .unwrap_or_default();
+
+        let name = match sanitize_file_name(&sample.name) {
+            name if !name.is_empty() => name,
+            _ if !source_name.is_empty() => source_name.to_owned(),
+            _ => index.to_string(),
+        };
+
+        self.parts
+            .iter()
+            .map(|p| match p {
+                TemplatePart::Text(text) => text.to_owned(),
+                TemplatePart::Index { width, zero_pad: true } => format!("{index:0width$}"),
+                TemplatePart::Index { width, zero_pad: false } => format!("{index:width$}"),
+                TemplatePart::Name => name.to_owned(),
+                TemplatePart::FileName => source_name.to_owned(),
+            })
+            .collect()
+    }
+}
+
+fn parse_field(template: &str, field: &str) -> Result<TemplatePart, Error> {
+    let (field_name, spec) = match field.split_once(':') {
+        Some((field_name, spec)) => (field_name, Some(spec)),
+        None => (field, None),
+    };
+
+    match (field_name, spec) {
+        ("index", None) => Ok(TemplatePart::Index { width: 0, zero_pad: false }),
+        ("index", Some(spec)) => spec
+            .parse::<usize>()
+            .map(|width| TemplatePart::Index { width, zero_pad: spec.starts_with('0') })
+            .map_err(|_| template_error(template, &format!("Invalid index width \"{spec}\""))),
+        ("name", None) => Ok(TemplatePart::Name),
+        ("file_name", None) => Ok(TemplatePart::FileName),
+        ("name" | "file_name", Some(_)) => Err(template_error(template, &format!("Field \"{field_name}\" doesn't take width"))),
+        _ => Err(template_error(template, &format!("Unknown field \"{field_name}\", expected index, name or file_name"))),
+    }
+}
+
+fn template_error(template: &str, message: &str) -> Error {
+    Error::NameTemplate {
+        template: template.to_owned(),
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template_error(template: &str) -> String {
+        match SampleNaming::new(template) {
+            Err(Error::NameTemplate { message, .. }) => message,
+            result => panic!("Expected template error for \"{template}\", got {result:?}"),
+        }
+    }
+
+    fn sample(name: &str, file_name: &str) -> SampleEntry {
+        SampleEntry {
+            name: name.to_owned(),
+            file_name: file_name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        let names = [
+            ("kick", "kick"),
+            ("..", ""),
+            (".", ""),
+            ("", ""),
+            ("   ", ""),
+            ("../kick", ".._kick"),
+            ("drums/kick", "drums_kick"),
+            ("drums\\kick", "drums_kick"),
+            ("c:kick?", "c_kick_"),
+            ("kick. ", "kick"),
+            ("con", "con_"),
+            ("CON", "CON_"),
+            ("con.wav", "con_.wav"),
+            ("aux.foo", "aux_.foo"),
+            ("com1.tar.gz", "com1_.tar.gz"),
+            ("nul .wav", "nul_ .wav"),
+            ("console", "console"),
+            ("com10", "com10"),
+            ("lpt", "lpt"),
+        ];
+
+        for (name, expected) in names {
+            assert_eq!(sanitize_file_name(name), expected, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn unique_names_ignore_case() {
+        let mut unique_names = UniqueNames::new();
+
+        let names = ["Kick", "kick", "KICK", "kick_1", "snare"]
+            .map(|n| unique_names.insert(n.to_owned()));
+
+        assert_eq!(names, ["Kick", "kick_1", "KICK_2", "kick_1_1", "snare"]);
+    }
+
+    #[test]
+    fn formats_template_fields() {
+        let bank = BankFile {
+            samples: vec![
+                sample("kick", "drums/kick_src.wav"),
+                sample("", "snare.wav"),
+                sample("", ""),
+                sample("KICK", "kick2.wav"),
+            ],
+            ..Default::default()
+        };
+
+        let file_names = |template| SampleNaming::new(template).unwrap().file_names(&bank);
+
+        assert_eq!(file_names("{name}"), ["kick.wav", "snare.wav", "2.wav", "KICK_1.wav"]);
+        assert_eq!(file_names("{index:03}_{name}"), ["000_kick.wav", "001_snare.wav", "002_2.wav", "003_KICK.wav"]);
+        assert_eq!(file_names("{file_name}-{index:2}"), ["kick_src- 0.wav", "snare- 1.wav", "- 2.wav", "kick2- 3.wav"]);
+        assert_eq!(file_names("{{{index}}}"), ["{0}.wav", "{1}.wav", "{2}.wav", "{3}.wav"]);
+
+        // Nothing usable left falls back to index
+        assert_eq!(file_names("/"), ["_.wav", "__1.wav", "__2.wav", "__3.wav"]);
+        assert_eq!(file_names(".."), ["0.wav", "1.wav", "2.wav", "3.wav"]);
+    }
+
+    #[test]
+    fn invalid_templates_error() {
+        assert_eq!(template_error("{name"), "Unmatched '{'");
+        assert_eq!(template_error("name}"), "Unmatched '}'");
+        assert_eq!(template_error("{index:x}"), "Invalid index width \"x\"");
+        assert_eq!(template_error("{name:3}"), "Field \"name\" doesn't take width");
+        assert_eq!(template_error("{size}"), "Unknown field \"size\", expected index, name or file_name");
+    }
+}
@@ -0,0 +1,437 @@
+use crate::bank::*;
+use crate::Error;
+use crate::song::*;
+use crate::timeline::*;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+pub const DEFAULT_RENDER_SAMPLE_RATE: u32 = 48000;
+const RELEASE_SECS: f32 = 0.1; // Fixed fade after note off, SDES envelope isn't decoded (see `SdesEntry`)
+
+#[derive(Debug)]
+pub struct RenderOptions {
+    pub sample_rate: u32,
+    pub tail_secs: f64, // Silence kept after last note ends
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: DEFAULT_RENDER_SAMPLE_RATE,
+            tail_secs: 2.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RenderStem {
+    pub name: String,
+    pub pcm: Vec<i16>,
+}
+
+#[derive(Debug)]
+pub struct RenderNote {
+    pub start_secs: f64,
+    pub length_secs: f64,
+    pub bank_index: usize, // Bank of song timeline, ignored when rendering with single bank
+    pub bank_num: u8,
+    pub prog: u16,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+/// Plays notes through key zones of bank using decoded samples
+pub struct BankRenderer<'a> {
+    bank: &'a BankFile,
+    samples: Vec<DecodedSample>,
+}
+
+impl<'a> BankRenderer<'a> {
+    pub fn new<T: Read + Seek>(bank: &'a BankFile, sample_reader: &mut T) -> Result<Self, Error> {
+        let samples = (0..bank.samples.len())
+            .map(|i| bank.decode_sample_mono(sample_reader, i))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            bank,
+            samples,
+        })
+    }
+
+    /// Number of stereo frames needed to render notes
+    pub fn frame_count(notes: &[RenderNote], options: &RenderOptions) -> usize {
+        let end_secs = notes
+            .iter()
+            .map(|n| n.start_secs + n.length_secs)
+            .fold(0.0, f64::max);
+
+        ((end_secs + options.tail_secs) * options.sample_rate as f64).ceil() as usize
+    }
+
+    /// Mixes notes into interleaved stereo buffer
+    pub fn render(&self, notes: &[RenderNote], options: &RenderOptions) -> Vec<f32> {
+        self.render_frames(notes, Self::frame_count(notes, options), options)
+    }
+
+    /// Mixes notes into interleaved stereo buffer of fixed length
+    pub fn render_frames(&self, notes: &[RenderNote], frame_count: usize, options: &RenderOptions) -> Vec<f32> {
+        let mut mix = vec![0f32; frame_count * 2];
+
+        for note in notes.iter() {
+            self.mix_note(note, &mut mix, options.sample_rate);
+        }
+
+        mix
+    }
+
+    fn mix_note(&self, note: &RenderNote, mix: &mut [f32], sample_rate: u32) {
+        let out_rate = sample_rate as f64;
+        let start_frame = (note.start_secs * out_rate) as usize;
+        let held_frames = (note.length_secs * out_rate) as usize;
+
+        for zone in self.bank.resolve(note.bank_num, note.prog, note.pitch, note.velocity) {
+            let decoded = &self.samples[zone.sample_index];
+            let data = &decoded.pcm;
+
+            if data.is_empty() {
+                continue;
+            }
+
+            let loop_range = match (decoded.loop_start, decoded.loop_end) {
+                // Decoded data stops at loop end, so end can be whole length
+                (Some(start), Some(end)) if start < end && (end as usize) <= data.len() => Some((start as f64, end as f64)),
+                _ => None,
+            };
+
+            let step = zone.playback_rate as f64 * zone.sample.sample_rate as f64 / out_rate;
+            let release_frames = (RELEASE_SECS as f64 * out_rate) as usize;
+
+            // Constant power pan
+            let angle = (zone.pan + 1.) * std::f32::consts::FRAC_PI_4;
+            let (left_gain, right_gain) = (angle.cos() * zone.gain, angle.sin() * zone.gain);
+
+            let frame_count = held_frames + release_frames;
+
+            for i in 0..frame_count {
+                let mut pos = i as f64 * step;
+
+                // Wrap back into loop once past loop end
+                if let Some((loop_start, loop_end)) = loop_range.filter(|(_, end)| pos >= *end) {
+                    pos = loop_start + (pos - loop_start) % (loop_end - loop_start);
+                }
+
+                let index = pos as usize;
+
+                if index >= data.len() {
+                    break;
+                }
+
+                // Sample after last frame of loop is loop start, last frame of one-shot holds its value
+                let next_index = match loop_range {
+                    Some((loop_start, loop_end)) if index + 1 >= loop_end as usize => loop_start as usize,
+                    _ => (index + 1).min(data.len() - 1),
+                };
+
+                let out_index = (start_frame + i) * 2;
+                if out_index + 1 >= mix.len() {
+                    break;
+                }
+
+                // Linear interpolation between source samples
+                let frac = (pos - index as f64) as f32;
+                let value = (data[index] as f32 * (1. - frac) + data[next_index] as f32 * frac) / i16::MAX as f32;
+
+                // Fade out after note off
+                let env = match i.checked_sub(held_frames) {
+                    Some(r) => 1. - (r as f32 / release_frames.max(1) as f32),
+                    None => 1.,
+                };
+
+                mix[out_index] += value * left_gain * env;
+                mix[out_index + 1] += value * right_gain * env;
+            }
+        }
+    }
+}
+
+/// Plays notes through banks of song timeline, each note uses bank playing when it starts
+pub struct SongRenderer<'a> {
+    renderers: Vec<BankRenderer<'a>>,
+}
+
+impl<'a> SongRenderer<'a> {
+    pub fn new(timeline: &'a BankTimeline) -> Result<Self, Error> {
+        let renderers = timeline.banks
+            .iter()
+            .map(|b| BankRenderer::new(&b.bank, &mut b.open_sample_file()?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            renderers,
+        })
+    }
+
+    /// Mixes notes into interleaved stereo buffer
+    pub fn render(&self, notes: &[RenderNote], options: &RenderOptions) -> Vec<f32> {
+        self.render_frames(notes, BankRenderer::frame_count(notes, options), options)
+    }
+
+    /// Mixes notes into interleaved stereo buffer of fixed length
+    pub fn render_frames(&self, notes: &[RenderNote], frame_count: usize, options: &RenderOptions) -> Vec<f32> {
+        let mut mix = vec![0f32; frame_count * 2];
+
+        for note in notes.iter() {
+            if let Some(renderer) = self.renderers.get(note.bank_index) {
+                renderer.mix_note(note, &mut mix, options.sample_rate);
+            }
+        }
+
+        mix
+    }
+}
+
+/// Converts mix to 16-bit pcm, scaling down if mix clips
+pub fn mix_to_pcm(mix: &[f32]) -> Vec<i16> {
+    mix_to_pcm_with_peak(mix, mix_peak(mix))
+}
+
+/// Loudest absolute value in mix, at least 1.0
+pub fn mix_peak(mix: &[f32]) -> f32 {
+    mix
+        .iter()
+        .fold(1f32, |acc, s| acc.max(s.abs()))
+}
+
+/// Converts mix to 16-bit pcm, scaling by peak (shared peak keeps stems consistent)
+pub fn mix_to_pcm_with_peak(mix: &[f32], peak: f32) -> Vec<i16> {
+    mix
+        .iter()
+        .map(|s| ((s / peak) * i16::MAX as f32) as i16)
+        .collect()
+}
+
+/// Collects notes from all instrument lanes, played with program of channel through bank playing at note start
+pub fn get_render_notes(song: &AmpSong, timeline: &BankTimeline) -> Vec<RenderNote> {
+    get_track_render_notes(song, timeline)
+        .into_iter()
+        .flat_map(|(_, notes)| notes)
+        .collect()
+}
+
+/// Collects notes for each instrument lane, named by lane
+pub fn get_track_render_notes(song: &AmpSong, timeline: &BankTimeline) -> Vec<(String, Vec<RenderNote>)> {
+    song.lanes
+        .iter()
+        .map(|lane| (lane.name.to_owned(), get_lane_notes(lane, timeline)))
+        .collect()
+}
+
+fn get_lane_notes(lane: &InstrumentLane, timeline: &BankTimeline) -> Vec<RenderNote> {
+    lane.notes
+        .iter()
+        .map(|note| {
+            let bank_index = timeline.bank_index_at(note.pos_secs);
+            let prog = lane.program_at(note.channel, note.pos) as u16;
+
+            // Midi has no bank select, so use bank entry of .bnk with program
+            let bank_num = timeline.banks
+                .get(bank_index)
+                .and_then(|b| b.bank.find_prog_bank(prog))
+                .map(|b| b.bank_num)
+                .unwrap_or_default();
+
+            RenderNote {
+                start_secs: note.pos_secs,
+                length_secs: note.length_secs,
+                bank_index,
+                bank_num,
+                prog,
+                pitch: note.pitch,
+                velocity: note.velocity,
+            }
+        })
+        .collect()
+}
+
+/// Renders song midi to interleaved stereo pcm, switching banks as BANK track does
+pub fn render_song<T: AsRef<Path>>(mid_path: T, options: &RenderOptions) -> Result<Vec<i16>, Error> {
+    let (song, timeline) = open_song(mid_path)?;
+
+    let renderer = SongRenderer::new(&timeline)?;
+    let notes = get_render_notes(&song, &timeline);
+
+    let mix = renderer.render(&notes, options);
+    Ok(mix_to_pcm(&mix))
+}
+
+/// Renders each track of song midi to its own stem, all with same length and scale as full mix
+pub fn render_song_stems<T: AsRef<Path>>(mid_path: T, options: &RenderOptions) -> Result<Vec<RenderStem>, Error> {
+    let (song, timeline) = open_song(mid_path)?;
+
+    let renderer = SongRenderer::new(&timeline)?;
+    let track_notes = get_track_render_notes(&song, &timeline);
+
+    let frame_count = track_notes
+        .iter()
+        .map(|(_, notes)| BankRenderer::frame_count(notes, options))
+        .max()
+        .unwrap_or_default();
+
+    let stem_mixes = track_notes
+        .iter()
+        .map(|(_, notes)| renderer.render_frames(notes, frame_count, options))
+        .collect::<Vec<_>>();
+
+    // Use peak of full mix so stems sum back to same mix
+    let mut full_mix = vec![0f32; frame_count * 2];
+    for stem_mix in stem_mixes.iter() {
+        for (f, s) in full_mix.iter_mut().zip(stem_mix.iter()) {
+            *f += s;
+        }
+    }
+
+    let peak = mix_peak(&full_mix);
+
+    let stems = track_notes
+        .into_iter()
+        .zip(stem_mixes)
+        .map(|((name, _), mix)| RenderStem {
+            name,
+            pcm: mix_to_pcm_with_peak(&mix, peak),
+        })
+        .collect();
+
+    Ok(stems)
+}
+
+fn open_song<T: AsRef<Path>>(mid_path: T) -> Result<(AmpSong, BankTimeline), Error> {
+    let mid_path = mid_path.as_ref();
+
+    let song = AmpSong::from_file(mid_path)?;
+    let timeline = BankTimeline::from_song(&song, mid_path)?;
+
+    Ok((song, timeline))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::*;
+    use crate::song::tests::*;
+    use std::io::Cursor;
+
+    const RATE: u32 = 22050;
+
+    // Fixture bank with zone playing looped sample, or one-shot sample with same pcm
+    fn test_bank(looped: bool) -> (BankFile, Vec<u8>) {
+        let (mut bank, mut sample_data) = looped_bank();
+
+        if !looped {
+            push_one_shot_sample(&mut bank, &mut sample_data);
+            bank.sdes[0].samp = 1;
+        }
+
+        (bank, sample_data)
+    }
+
+    fn render_note(looped: bool, length_secs: f64) -> Vec<f32> {
+        let (bank, sample_data) = test_bank(looped);
+        let renderer = BankRenderer::new(&bank, &mut Cursor::new(&sample_data)).unwrap();
+
+        let note = RenderNote {
+            start_secs: 0.,
+            length_secs,
+            bank_index: 0,
+            bank_num: 1,
+            prog: 3,
+            pitch: 46, // Base pitch less transpose, plays sample at recorded pitch
+            velocity: 127,
+        };
+
+        let options = RenderOptions {
+            sample_rate: RATE,
+            tail_secs: 0.,
+        };
+
+        renderer.render(&[note], &options)
+    }
+
+    fn peak(frames: &[f32]) -> f32 {
+        frames.iter().fold(0f32, |p, s| p.max(s.abs()))
+    }
+
+    #[test]
+    fn looped_sample_plays_past_loop_end() {
+        let loop_end = LOOP_END as usize;
+        let mix = render_note(true, 0.5);
+
+        // Held for 0.5s, many times longer than loop
+        let held_frames = RATE as usize / 2;
+        assert!(held_frames > loop_end * 10);
+
+        for frames in mix[(loop_end * 2)..(held_frames * 2)].chunks(loop_end * 2) {
+            assert!(peak(frames) > 0.1, "silent after loop end");
+        }
+    }
+
+    #[test]
+    fn one_shot_sample_stops_at_end() {
+        let sample_len = LOOP_END as usize;
+        let mix = render_note(false, 0.5);
+
+        assert!(peak(&mix[..(sample_len * 2)]) > 0.1);
+        assert_eq!(peak(&mix[(sample_len * 2)..]), 0.);
+    }
+
+    #[test]
+    fn stems_sum_to_full_mix() {
+        let dir = std::env::temp_dir().join(format!("amp_render_stems_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (bank, sample_data) = test_bank(false);
+        bank.write_to_file(dir.join("test.bnk")).unwrap();
+        std::fs::write(dir.join("test.nse"), &sample_data).unwrap();
+
+        // 120 bpm default tempo, so quarter note (480 ticks) is 0.5s, both tracks play program 3
+        let bank = meta(&[0], 0x01, b"test.bnk");
+        let lead = [0x00, 0xC0, 3, 0x00, 0x90, 60, 100, 0x83, 0x60, 0x80, 60, 0];
+        let bass = [0x00, 0xC1, 3, 0x83, 0x60, 0x91, 60, 100, 0x83, 0x60, 0x81, 60, 0];
+
+        let mid_data = smf(&[
+            named_track("BANK", &bank),
+            named_track("lead", &lead),
+            named_track("bass", &bass),
+        ]);
+
+        let mid_path = dir.join("test.mid");
+        std::fs::write(&mid_path, mid_data).unwrap();
+
+        let options = RenderOptions {
+            sample_rate: RATE,
+            tail_secs: 0.,
+        };
+
+        let full_mix = render_song(&mid_path, &options).unwrap();
+        let stems = render_song_stems(&mid_path, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let names = stems.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["lead", "bass"]);
+
+        // Second note starts at 0.5s
+        let split = RATE as usize;
+        let (lead, bass) = (&stems[0].pcm, &stems[1].pcm);
+
+        assert_eq!(lead.len(), full_mix.len());
+        assert_eq!(bass.len(), full_mix.len());
+        assert!(lead[..split].iter().any(|s| *s != 0));
+        assert!(lead[split..].iter().all(|s| *s == 0));
+        assert!(bass[..split].iter().all(|s| *s == 0));
+        assert!(bass[split..].iter().any(|s| *s != 0));
+
+        for (i, f) in full_mix.iter().enumerate() {
+            let sum = lead[i] as i32 + bass[i] as i32;
+            assert!((*f as i32 - sum).abs() <= 1, "Frame {i}: {f} != {sum}");
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use crate::Error;
+use grim::midi::{MidiEvent, MidiFile, MidiNote, MidiText, MidiTextType};
+use std::path::Path;
+
+pub const BANK_TRACK_NAME: &str = "BANK";
+pub const GEM_LANE_COUNT: u8 = 3;
+
+// Default tempo when midi has no tempo events (120 bpm)
+const DEFAULT_MPQ: u32 = 500_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct SongNote {
+    pub pos: u64,
+    pub pos_secs: f64,
+    pub length: u64,
+    pub length_secs: f64,
+    pub channel: u8,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+impl From<&MidiNote> for SongNote {
+    fn from(note: &MidiNote) -> Self {
+        Self {
+            pos: note.pos,
+            pos_secs: note.pos_realtime.unwrap_or_default() / 1000.,
+            length: note.length,
+            length_secs: note.length_realtime.unwrap_or_default() / 1000.,
+            channel: note.channel,
+            pitch: note.pitch,
+            velocity: note.velocity,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Gem {
+    pub pos: u64,
+    pub pos_secs: f64,
+    pub length: u64,
+    pub length_secs: f64,
+    pub lane: u8, // 0 = left, 1 = middle, 2 = right
+}
+
+/// Program change event in instrument track
+#[derive(Clone, Debug, Default)]
+pub struct ProgramChange {
+    pub pos: u64,
+    pub channel: u8,
+    pub program: u8,
+}
+
+/// Instrument track of song, notes are played through bank and also hold gems
+#[derive(Clone, Debug, Default)]
+pub struct InstrumentLane {
+    pub name: String,
+    pub track_index: usize,
+    pub notes: Vec<SongNote>,
+    pub program_changes: Vec<ProgramChange>, // In song order
+}
+
+impl InstrumentLane {
+    /// Program playing on channel at position, channels start on program 0 (midi default)
+    pub fn program_at(&self, channel: u8, pos: u64) -> u8 {
+        self.program_changes
+            .iter()
+            .rev()
+            .find(|pc| pc.channel == channel && pc.pos <= pos)
+            .map(|pc| pc.program)
+            .unwrap_or_default()
+    }
+
+    /// Gems from notes on `GEM_LANE_COUNT` keys starting at base pitch, in song order
+    ///
+    /// Pitches of each difficulty aren't verified against retail songs yet, so base pitch is up to caller
+    pub fn gems_at(&self, base_pitch: u8) -> Vec<Gem> {
+        self.notes
+            .iter()
+            .filter(|n| n.pitch >= base_pitch && (n.pitch - base_pitch) < GEM_LANE_COUNT)
+            .map(|n| Gem {
+                pos: n.pos,
+                pos_secs: n.pos_secs,
+                length: n.length,
+                length_secs: n.length_secs,
+                lane: n.pitch - base_pitch,
+            })
+            .collect()
+    }
+}
+
+/// Text event in BANK track, bank (.bnk file name) used from this point on
+#[derive(Clone, Debug, Default)]
+pub struct BankSwitch {
+    pub pos: u64,
+    pub pos_secs: f64,
+    pub bank_name: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TempoChange {
+    pub pos: u64,
+    pub pos_secs: f64,
+    pub mpq: u32, // Microseconds per quarter note
+}
+
+impl TempoChange {
+    pub fn bpm(&self) -> f64 {
+        60_000_000. / self.mpq.max(1) as f64
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SectionMarker {
+    pub pos: u64,
+    pub pos_secs: f64,
+    pub name: String,
+}
+
+/// Song midi read as instrument lanes, bank switches, tempo map and sections
+///
+/// Every track with notes except BANK is an instrument lane, named by track name (or index if unnamed).
+/// Tracks without notes (i.e. tempo track) are skipped. Sections come from marker events in any track.
+pub struct AmpSong {
+    midi: MidiFile,
+    pub lanes: Vec<InstrumentLane>,
+    pub bank_switches: Vec<BankSwitch>,
+    pub tempo_map: Vec<TempoChange>,
+    pub sections: Vec<SectionMarker>,
+}
+
+impl AmpSong {
+    pub fn from_file<T: AsRef<Path>>(mid_path: T) -> Result<Self, Error> {
+        let mid_path = mid_path.as_ref();
+
+        let midi = MidiFile::from_path(mid_path)
+            .map_err(|e| Error::Midi(e.to_string()))?;
+
+        // Program changes aren't exposed by MidiFile so they're read from track data
+        let mid_data = std::fs::read(mid_path)?;
+        let track_program_changes = read_program_changes(&mid_data)?;
+
+        let mut song = Self::from_midi(midi)?;
+
+        for lane in song.lanes.iter_mut() {
+            if let Some(program_changes) = track_program_changes.get(lane.track_index) {
+                lane.program_changes = program_changes.to_owned();
+            }
+        }
+
+        Ok(song)
+    }
+
+    /// Reads song from midi, BANK track with at least one bank switch and one other track with notes are required
+    ///
+    /// Program changes aren't available from `MidiFile` so lanes play program 0, use `from_file` to read them
+    pub fn from_midi(midi: MidiFile) -> Result<Self, Error> {
+        let bank_track = midi.tracks
+            .iter()
+            .find(|t| t.name
+                .as_ref()
+                .is_some_and(|n| n.as_str().eq(BANK_TRACK_NAME)))
+            .ok_or_else(|| Error::MissingTrack {
+                name: String::from(BANK_TRACK_NAME),
+            })?;
+
+        let bank_switches = bank_track
+            .events
+            .iter()
+            .flat_map(|e| match e {
+                MidiEvent::Meta(mt @ MidiText { pos, pos_realtime, .. }) if mt.is_text()
+                    => mt.as_str().map(|s| BankSwitch {
+                        pos: *pos,
+                        pos_secs: pos_realtime.unwrap_or_default() / 1000.,
+                        bank_name: s.to_owned(),
+                    }),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+
+        if bank_switches.is_empty() {
+            return Err(Error::Midi(String::from("No bank events found in BANK track")));
+        }
+
+        let lanes = midi.tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !t.name
+                .as_ref()
+                .is_some_and(|n| n.as_str().eq(BANK_TRACK_NAME)))
+            .map(|(i, t)| InstrumentLane {
+                name: t.name
+                    .clone()
+                    .unwrap_or_else(|| format!("track_{i}")),
+                track_index: i,
+                notes: t.events
+                    .iter()
+                    .flat_map(|e| match e {
+                        MidiEvent::Note(note) => Some(note.into()),
+                        _ => None
+                    })
+                    .collect(),
+                program_changes: Vec::new(),
+            })
+            .filter(|l| !l.notes.is_empty())
+            .collect::<Vec<_>>();
+
+        if lanes.is_empty() {
+            return Err(Error::Midi(String::from("No instrument tracks found")));
+        }
+
+        let mut tempo_map = midi.tempo
+            .iter()
+            .map(|t| TempoChange {
+                pos: t.pos,
+                pos_secs: t.pos_realtime.unwrap_or_default() / 1000.,
+                mpq: t.mpq,
+            })
+            .collect::<Vec<_>>();
+
+        if tempo_map.first().filter(|t| t.pos == 0).is_none() {
+            tempo_map.insert(0, TempoChange {
+                mpq: DEFAULT_MPQ,
+                ..Default::default()
+            });
+        }
+
+        let mut sections = midi.tracks
+            .iter()
+            .flat_map(|t| t.events.iter())
+            .flat_map(|e| match e {
+                MidiEvent::Meta(mt @ MidiText { pos, pos_realtime, text: MidiTextType::Marker(_), .. })
+                    => mt.as_str().map(|s| SectionMarker {
+                        pos: *pos,
+                        pos_secs: pos_realtime.unwrap_or_default() / 1000.,
+                        name: s.to_owned(),
+                    }),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+
+        sections.sort_by_key(|s| s.pos);
+
+        Ok(Self {
+            midi,
+            lanes,
+            bank_switches,
+            tempo_map,
+            sections,
+        })
+    }
+
+    pub fn midi(&self) -> &MidiFile {
+        &self.midi
+    }
+
+    pub fn ticks_per_quarter(&self) -> u16 {
+        self.midi.ticks_per_quarter
+    }
+
+    pub fn lane(&self, name: &str) -> Option<&InstrumentLane> {
+        self.lanes
+            .iter()
+            .find(|l| l.name.eq(name))
+    }
+
+    /// Length of song in seconds, up to end of last note
+    pub fn length_secs(&self) -> f64 {
+        self.lanes
+            .iter()
+            .flat_map(|l| l.notes.iter())
+            .map(|n| n.pos_secs + n.length_secs)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Reads program change events of each track (MTrk chunk) from standard midi file data
+fn read_program_changes(mid_data: &[u8]) -> Result<Vec<Vec<ProgramChange>>, Error> {
+    let mut tracks = Vec::new();
+    let mut offset = 0;
+
+    while offset + 8 <= mid_data.len() {
+        let tag = &mid_data[offset..(offset + 4)];
+        let size = u32::from_be_bytes(mid_data[(offset + 4)..(offset + 8)].try_into().unwrap()) as usize;
+
+        let track_data = mid_data
+            .get((offset + 8)..)
+            .and_then(|d| d.get(..size))
+            .ok_or_else(|| Error::Midi("Chunk extends past end of file".into()))?;
+
+        if tag == b"MTrk" {
+            tracks.push(read_track_program_changes(track_data)?);
+        }
+
+        offset += 8 + size;
+    }
+
+    Ok(tracks)
+}
+
+fn read_track_program_changes(track_data: &[u8]) -> Result<Vec<ProgramChange>, Error> {
+    let mut program_changes = Vec::new();
+    let mut pos = 0u64;
+    let mut offset = 0;
+    let mut running_status = 0u8;
+
+    let read_u8 = |offset: &mut usize| -> Result<u8, Error> {
+        let b = *track_data
+            .get(*offset)
+            .ok_or_else(|| Error::Midi("Track event extends past end of track".into()))?;
+
+        *offset += 1;
+        Ok(b)
+    };
+
+    while offset < track_data.len() {
+        // Delta time as variable length quantity
+        let mut delta = 0u64;
+
+        loop {
+            let b = read_u8(&mut offset)?;
+            delta = (delta << 7) | (b & 0x7F) as u64;
+
+            if (b & 0x80) == 0 {
+                break;
+            }
+        }
+
+        pos += delta;
+
+        let mut status = read_u8(&mut offset)?;
+
+        // Running status reuses last channel status, data byte was already read
+        if status < 0x80 {
+            status = running_status;
+            offset -= 1;
+        }
+
+        match status {
+            0xFF | 0xF0 | 0xF7 => {
+                if status == 0xFF {
+                    read_u8(&mut offset)?; // Meta type
+                }
+
+                let mut size = 0usize;
+
+                loop {
+                    let b = read_u8(&mut offset)?;
+                    size = (size << 7) | (b & 0x7F) as usize;
+
+                    if (b & 0x80) == 0 {
+                        break;
+                    }
+                }
+
+                offset = offset.saturating_add(size);
+            },
+            0x80..=0xEF => {
+                running_status = status;
+                let data_1 = read_u8(&mut offset)?;
+
+                match status & 0xF0 {
+                    0xC0 => program_changes.push(ProgramChange {
+                        pos,
+                        channel: status & 0x0F,
+                        program: data_1,
+                    }),
+                    0xD0 => {},
+                    _ => {
+                        read_u8(&mut offset)?;
+                    }
+                }
+            },
+            _ => return Err(Error::Midi(format!("Unknown midi status 0x{status:02X}"))),
+        }
+    }
+
+    Ok(program_changes)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn smf_chunk(tag: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [tag.as_slice(), &(data.len() as u32).to_be_bytes(), data].concat()
+    }
+
+    // Meta event after delta (already variable length encoded)
+    pub(crate) fn meta(delta: &[u8], kind: u8, data: &[u8]) -> Vec<u8> {
+        [delta, &[0xFF, kind, data.len() as u8], data].concat()
+    }
+
+    pub(crate) fn end_of_track() -> Vec<u8> {
+        meta(&[0], 0x2F, &[])
+    }
+
+    // Track data starting with name, events start with delta from name
+    pub(crate) fn named_track(name: &str, events: &[u8]) -> Vec<u8> {
+        [meta(&[0], 0x03, name.as_bytes()), events.to_vec(), end_of_track()].concat()
+    }
+
+    // Format 1 midi (480 ticks per quarter)
+    pub(crate) fn smf(tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut mid_data = smf_chunk(b"MThd", &[0, 1, 0, tracks.len() as u8, 0x01, 0xE0]);
+
+        for track in tracks {
+            mid_data.extend(smf_chunk(b"MTrk", track));
+        }
+
+        mid_data
+    }
+
+    // Reads song from midi written to temp file, name keeps file apart from other tests
+    pub(crate) fn read_song(name: &str, tracks: &[Vec<u8>]) -> Result<AmpSong, Error> {
+        let mid_path = std::env::temp_dir().join(format!("amp_song_{name}_{}.mid", std::process::id()));
+        std::fs::write(&mid_path, smf(tracks)).unwrap();
+
+        let song = AmpSong::from_file(&mid_path);
+        std::fs::remove_file(&mid_path).unwrap();
+
+        song
+    }
+
+    fn bank_track() -> Vec<u8> {
+        [
+            meta(&[0], 0x03, b"BANK"),
+            meta(&[0], 0x01, b"amp_drums.bnk"),
+            meta(&[0x86, 0x00], 0x01, b"amp_drums2.bnk"), // At 768
+            end_of_track(),
+        ].concat()
+    }
+
+    #[test]
+    fn reads_song_lanes_gems_banks_tempo_and_sections() {
+        let conductor = [
+            vec![0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20], // 120 bpm
+            meta(&[0], 0x06, b"intro"),
+            meta(&[0x83, 0x00], 0x06, b"verse"), // At 384
+            end_of_track(),
+        ].concat();
+
+        let drums = named_track("drums", &[
+            0x00, 0xC0, 0x03, // Program 3
+            0x00, 0x90, 60, 100, // Left gem from 60
+            0x00, 0x90, 40, 100, // Not a gem
+            0x83, 0x60, 0x80, 60, 0, // Off at 480
+            0x00, 0x80, 40, 0,
+            0x00, 0x90, 73, 90, // Middle gem from 72 at 480
+            0x00, 0x90, 98, 80, // Right gem from 96 at 480
+            0x81, 0x70, 0x80, 73, 0, // Off at 720
+            0x00, 0x80, 98, 0,
+        ]);
+
+        let bass = named_track("bass", &[]);
+
+        let song = read_song("full", &[conductor, bank_track(), drums, bass]).unwrap();
+
+        assert_eq!(song.ticks_per_quarter(), 480);
+
+        let lanes = song.lanes
+            .iter()
+            .map(|l| (l.name.as_str(), l.track_index, l.notes.len()))
+            .collect::<Vec<_>>();
+
+        // Tempo track and tracks without notes are skipped
+        assert_eq!(lanes, [("drums", 2, 4)]);
+        assert!(song.lane("bass").is_none());
+
+        let drums = song.lane("drums").unwrap();
+        assert_eq!(drums.program_at(0, 480), 3);
+
+        let gems = |base_pitch| drums
+            .gems_at(base_pitch)
+            .iter()
+            .map(|g| (g.pos, g.length, g.lane))
+            .collect::<Vec<_>>();
+
+        assert_eq!(gems(60), [(0, 480, 0)]);
+        assert_eq!(gems(72), [(480, 240, 1)]);
+        assert!(gems(84).is_empty());
+        assert_eq!(gems(96), [(480, 240, 2)]);
+        assert_eq!(gems(40).len(), 1);
+
+        let banks = song.bank_switches
+            .iter()
+            .map(|b| (b.pos, b.pos_secs, b.bank_name.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(banks, [(0, 0., "amp_drums.bnk"), (768, 0.8, "amp_drums2.bnk")]);
+
+        assert_eq!(song.tempo_map.len(), 1);
+        assert_eq!(song.tempo_map[0].bpm(), 120.);
+
+        let sections = song.sections
+            .iter()
+            .map(|s| (s.pos, s.name.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(sections, [(0, "intro"), (384, "verse")]);
+        assert_eq!(song.length_secs(), 0.75);
+    }
+
+    #[test]
+    fn missing_bank_track_errors() {
+        let drums = named_track("drums", &[0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0]);
+        let err = read_song("no_bank", &[drums]).err().unwrap();
+
+        assert!(matches!(err, Error::MissingTrack { ref name } if name == BANK_TRACK_NAME), "{err:?}");
+    }
+
+    #[test]
+    fn missing_instrument_tracks_errors() {
+        let conductor = [vec![0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20], end_of_track()].concat();
+        let bass = named_track("bass", &[]);
+
+        let err = read_song("no_lanes", &[conductor, bank_track(), bass]).err().unwrap();
+
+        assert!(matches!(err, Error::Midi(_)), "{err:?}");
+    }
+
+    #[test]
+    fn reads_program_changes_per_track() {
+        let conductor = [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // Tempo
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let track = [
+            0x00, 0xFF, 0x03, 0x04, b'd', b'r', b'u', b'm', // Track name
+            0x00, 0xC1, 0x05, // Channel 1 program 5
+            0x00, 0x91, 0x3C, 0x64,
+            0x60, 0x3C, 0x00, // Running status note off
+            0x00, 0xD1, 0x40, // Channel pressure, single data byte
+            0x00, 0xF0, 0x02, 0x7E, 0xF7, // Sysex
+            0x81, 0x40, 0xC2, 0x07, // Channel 2 program 7 at 288
+            0x10, 0xC1, 0x09, // Channel 1 program 9 at 304
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let mid_data = [
+            smf_chunk(b"MThd", &[0, 1, 0, 2, 0, 96]),
+            smf_chunk(b"MTrk", &conductor),
+            smf_chunk(b"MTrk", &track),
+        ].concat();
+
+        let tracks = read_program_changes(&mid_data).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert!(tracks[0].is_empty());
+
+        let lane = InstrumentLane {
+            program_changes: tracks[1].to_owned(),
+            ..Default::default()
+        };
+
+        let changes = lane.program_changes
+            .iter()
+            .map(|pc| (pc.pos, pc.channel, pc.program))
+            .collect::<Vec<_>>();
+
+        assert_eq!(changes, [(0, 1, 5), (288, 2, 7), (304, 1, 9)]);
+
+        assert_eq!(lane.program_at(1, 100), 5);
+        assert_eq!(lane.program_at(1, 304), 9);
+        assert_eq!(lane.program_at(2, 287), 0);
+        assert_eq!(lane.program_at(2, 288), 7);
+        assert_eq!(lane.program_at(0, 500), 0);
+    }
+
+    #[test]
+    fn truncated_track_errors() {
+        let mid_data = [
+            smf_chunk(b"MThd", &[0, 1, 0, 1, 0, 96]),
+            smf_chunk(b"MTrk", &[0x00, 0xC1]),
+        ].concat();
+
+        assert!(matches!(read_program_changes(&mid_data), Err(Error::Midi(_))));
+    }
+}
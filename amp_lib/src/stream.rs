@@ -0,0 +1,372 @@
+use crate::bank::*;
+use crate::vag::*;
+use crate::Error;
+use grim::audio::VAGDecoder;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Lazily decodes sample from .nse stream, one vag block at a time
+///
+/// Yields interleaved pcm. When looping, playback jumps back to loop start after loop end block
+/// (keeping decoder history like SPU2 does), so stream never ends.
+pub struct SampleStream<T: Read + Seek> {
+    reader: T,
+    pos: u64,
+    size: u64, // Sample data for all channels, up to next sample
+    channels: usize,
+    interleave_size: usize,
+    looping: bool,
+
+    decoders: Vec<VAGDecoder>,
+    channel_samples: Vec<[i16; VAG_SAMPLES_PER_BLOCK]>,
+    reader_pos: Option<u64>, // Skips seeking when reading consecutive blocks
+    next_block: usize, // Per channel
+    finished: bool,
+    buffer: Vec<i16>, // Last decoded block of each channel, interleaved
+    buffer_offset: usize,
+    buffer_frame_pos: u64, // Frames played before current block, including repeats of loop
+
+    loop_start_block: Option<usize>,
+    loop_end_block: Option<usize>,
+}
+
+impl BankFile {
+    /// Opens lazy decoder for sample in .nse stream
+    pub fn sample_stream<T: Read + Seek>(&self, mut sample_reader: T, index: usize) -> Result<SampleStream<T>, Error> {
+        let sample_file_len = sample_reader.seek(SeekFrom::End(0))?;
+        let range = self.sample_data_range(index, sample_file_len)?;
+
+        let pos = range.start;
+        let size = range.end - range.start;
+        let channels = self.samples[index].channels.max(1) as usize;
+
+        // Layout of multi-channel samples is found from end blocks, so needs whole sample data
+        let interleave_size = match channels {
+            1 => size as usize,
+            _ => {
+                let mut sample_data = vec![0u8; size as usize];
+                sample_reader.seek(SeekFrom::Start(pos))?;
+                sample_reader.read_exact(&mut sample_data)?;
+
+                sample_interleave_size(&sample_data, channels, index, pos)?
+            }
+        };
+
+        Ok(SampleStream {
+            reader: sample_reader,
+            pos,
+            size,
+            channels,
+            interleave_size: interleave_size.max(VAG_BYTES_PER_BLOCK),
+            looping: false,
+            decoders: (0..channels).map(|_| VAGDecoder::new()).collect(),
+            channel_samples: vec![[0i16; VAG_SAMPLES_PER_BLOCK]; channels],
+            reader_pos: None,
+            next_block: 0,
+            finished: false,
+            buffer: Vec::with_capacity(VAG_SAMPLES_PER_BLOCK * channels),
+            buffer_offset: 0,
+            buffer_frame_pos: 0,
+            loop_start_block: None,
+            loop_end_block: None,
+        })
+    }
+}
+
+impl<T: Read + Seek> SampleStream<T> {
+    /// Repeats loop (if sample has one) instead of ending at loop end
+    pub fn with_looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Frames played so far, including repeats of loop
+    pub fn frame_pos(&self) -> u64 {
+        self.buffer_frame_pos + (self.buffer_offset / self.channels) as u64
+    }
+
+    /// Loop start frame, known once loop start block is reached
+    pub fn loop_start(&self) -> Option<u32> {
+        self.loop_start_block
+            .filter(|_| self.loop_end_block.is_some())
+            .map(|b| (b * VAG_SAMPLES_PER_BLOCK) as u32)
+    }
+
+    /// Loop end frame (exclusive), known once loop end block is reached
+    pub fn loop_end(&self) -> Option<u32> {
+        self.loop_end_block
+            .map(|b| ((b + 1) * VAG_SAMPLES_PER_BLOCK) as u32)
+    }
+
+    /// Decodes next block of each channel, returning interleaved frames or None at end of sample
+    pub fn next_block(&mut self) -> Result<Option<&[i16]>, Error> {
+        // Skips anything left of current block
+        if !self.refill_buffer()? {
+            return Ok(None);
+        }
+
+        self.buffer_offset = self.buffer.len();
+        Ok(Some(&self.buffer))
+    }
+
+    /// Reads interleaved frames into buffer, returning number of frames read (0 at end of sample)
+    ///
+    /// Only whole frames are read, samples past last whole frame of buffer are left as-is
+    pub fn read_frames(&mut self, buffer: &mut [i16]) -> Result<usize, Error> {
+        let sample_count = buffer.len() - (buffer.len() % self.channels);
+        let mut count = 0;
+
+        while count < sample_count {
+            if self.buffer_offset >= self.buffer.len() && !self.refill_buffer()? {
+                break;
+            }
+
+            let available = &self.buffer[self.buffer_offset..];
+            let copy_count = available.len().min(sample_count - count);
+
+            buffer[count..(count + copy_count)].copy_from_slice(&available[..copy_count]);
+
+            count += copy_count;
+            self.buffer_offset += copy_count;
+        }
+
+        Ok(count / self.channels)
+    }
+
+    /// Seeks to frame, decoding from start of sample since each block depends on previous ones
+    pub fn seek_frame(&mut self, frame: u64) -> Result<(), Error> {
+        for decoder in self.decoders.iter_mut() {
+            *decoder = VAGDecoder::new();
+        }
+
+        self.next_block = 0;
+        self.finished = false;
+        self.buffer.clear();
+        self.buffer_offset = 0;
+        self.buffer_frame_pos = 0;
+
+        // Skip whole blocks, following loop if looping
+        while self.buffer_frame_pos + ((self.buffer.len() / self.channels) as u64) <= frame {
+            if !self.refill_buffer()? {
+                return Ok(());
+            }
+        }
+
+        self.buffer_offset = (frame - self.buffer_frame_pos) as usize * self.channels;
+        Ok(())
+    }
+
+    /// Replaces buffer with next decoded block, false at end of sample
+    fn refill_buffer(&mut self) -> Result<bool, Error> {
+        self.buffer_frame_pos += (self.buffer.len() / self.channels) as u64;
+        self.buffer.clear();
+        self.buffer_offset = 0;
+
+        self.decode_next_block()
+    }
+
+    fn decode_next_block(&mut self) -> Result<bool, Error> {
+        if self.finished {
+            return Ok(false);
+        }
+
+        let mut flags = 0;
+        let mut vag_block = [0u8; VAG_BYTES_PER_BLOCK];
+
+        let block_index = self.next_block;
+
+        for c in 0..self.channels {
+            if !self.read_block(c, block_index, &mut vag_block)? {
+                self.finished = true;
+                return Ok(false);
+            }
+
+            // First channel controls playback
+            if c == 0 {
+                flags = vag_block[1];
+
+                if flags == VAG_FLAG_STREAM_END {
+                    self.finished = true;
+                    return Ok(false);
+                }
+            }
+
+            self.channel_samples[c] = self.decoders[c].decode_block(&vag_block);
+        }
+
+        // Interleave channels
+        for i in 0..VAG_SAMPLES_PER_BLOCK {
+            self.buffer.extend(self.channel_samples.iter().map(|s| s[i]));
+        }
+
+        if (flags & VAG_FLAG_LOOP_START) != 0 && self.loop_start_block.is_none() {
+            self.loop_start_block = Some(block_index);
+        }
+
+        self.next_block += 1;
+
+        if (flags & VAG_FLAG_LOOP_END) != 0 {
+            // End block, either jumps back to loop start or stops playback
+            let loop_start = self.loop_start_block.filter(|_| (flags & VAG_FLAG_LOOP_REPEAT) != 0);
+
+            if loop_start.is_some() {
+                self.loop_end_block = Some(block_index);
+            }
+
+            match loop_start.filter(|_| self.looping) {
+                Some(start) => self.next_block = start,
+                None => self.finished = true,
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Reads block of channel, false if outside sample data
+    fn read_block(&mut self, channel: usize, block_index: usize, vag_block: &mut [u8; VAG_BYTES_PER_BLOCK]) -> Result<bool, Error> {
+        let channel_offset = block_index * VAG_BYTES_PER_BLOCK;
+        let row = channel_offset / self.interleave_size;
+
+        let offset = (row * self.channels + channel) * self.interleave_size + (channel_offset % self.interleave_size);
+
+        if (offset + VAG_BYTES_PER_BLOCK) as u64 > self.size {
+            return Ok(false);
+        }
+
+        let block_pos = self.pos + offset as u64;
+
+        if self.reader_pos != Some(block_pos) {
+            self.reader.seek(SeekFrom::Start(block_pos))?;
+        }
+
+        self.reader.read_exact(vag_block)?;
+        self.reader_pos = Some(block_pos + VAG_BYTES_PER_BLOCK as u64);
+
+        Ok(true)
+    }
+}
+
+impl<T: Read + Seek> Iterator for SampleStream<T> {
+    type Item = Result<i16, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer_offset >= self.buffer.len() {
+            match self.refill_buffer() {
+                Ok(true) => {},
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let sample = self.buffer[self.buffer_offset];
+        self.buffer_offset += 1;
+
+        Some(Ok(sample))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::{looped_bank, push_one_shot_sample};
+    use std::io::Cursor;
+
+    const LOOP_START: usize = crate::export::tests::LOOP_START as usize;
+    const LOOP_END: usize = crate::export::tests::LOOP_END as usize;
+
+    fn stream(bank: &BankFile, sample_data: &[u8], looping: bool) -> SampleStream<Cursor<Vec<u8>>> {
+        bank.sample_stream(Cursor::new(sample_data.to_vec()), 0)
+            .unwrap()
+            .with_looping(looping)
+    }
+
+    #[test]
+    fn read_frames_matches_decoded_sample() {
+        let (bank, sample_data) = looped_bank();
+        let decoded = bank.decode_sample(&mut Cursor::new(&sample_data), 0).unwrap();
+
+        let mut stream = stream(&bank, &sample_data, false);
+        let mut pcm = Vec::new();
+        let mut buffer = [0i16; 50];
+
+        // Buffer size doesn't line up with blocks
+        loop {
+            match stream.read_frames(&mut buffer).unwrap() {
+                0 => break,
+                count => pcm.extend_from_slice(&buffer[..count]),
+            }
+        }
+
+        assert_eq!(pcm.len(), LOOP_END);
+        assert_eq!(pcm, decoded.pcm);
+        assert_eq!(stream.frame_pos(), LOOP_END as u64);
+        assert_eq!((stream.loop_start(), stream.loop_end()), (Some(LOOP_START as u32), Some(LOOP_END as u32)));
+    }
+
+    #[test]
+    fn looping_stream_repeats_loop() {
+        let (bank, sample_data) = looped_bank();
+        let loop_len = LOOP_END - LOOP_START;
+
+        let pcm = stream(&bank, &sample_data, true)
+            .take(LOOP_END + loop_len * 4)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // Never ends, first pass is same as non-looping
+        assert_eq!(pcm.len(), LOOP_END + loop_len * 4);
+        assert_eq!(pcm[..LOOP_END], bank.decode_sample(&mut Cursor::new(&sample_data), 0).unwrap().pcm);
+
+        // Encoder uses filter 0 for loop start block, so history carried over jump doesn't change repeats
+        for repeat in pcm[LOOP_END..].chunks(loop_len) {
+            assert_eq!(repeat, &pcm[LOOP_START..LOOP_END]);
+        }
+    }
+
+    #[test]
+    fn seek_matches_reading_from_start() {
+        let (bank, sample_data) = looped_bank();
+        let loop_len = LOOP_END - LOOP_START;
+
+        let pcm = stream(&bank, &sample_data, true)
+            .take(LOOP_END + loop_len * 3)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut stream = stream(&bank, &sample_data, true);
+
+        // Within first block, at loop start, just before and past loop end, and later repeats
+        for frame in [5, LOOP_START, LOOP_END - 1, LOOP_END, LOOP_END + 30, LOOP_END + loop_len * 2 + 7, 0] {
+            stream.seek_frame(frame as u64).unwrap();
+            assert_eq!(stream.frame_pos(), frame as u64);
+
+            let mut buffer = [0i16; 10];
+            assert_eq!(stream.read_frames(&mut buffer).unwrap(), 10);
+            assert_eq!(buffer, pcm[frame..(frame + 10)], "frame {frame}");
+        }
+    }
+
+    #[test]
+    fn seek_past_end_of_one_shot_ends_stream() {
+        let (mut bank, mut sample_data) = looped_bank();
+        push_one_shot_sample(&mut bank, &mut sample_data);
+
+        let mut stream = bank.sample_stream(Cursor::new(sample_data), 1).unwrap();
+        stream.seek_frame(LOOP_END as u64 + 100).unwrap();
+
+        assert!(stream.next().is_none());
+        assert_eq!(stream.read_frames(&mut [0i16; 10]).unwrap(), 0);
+    }
+
+    #[test]
+    fn sample_past_end_of_data_errors() {
+        let (mut bank, sample_data) = looped_bank();
+        bank.samples[0].pos = sample_data.len() as u32;
+
+        let err = bank.sample_stream(Cursor::new(sample_data), 0).err().unwrap();
+        assert!(matches!(err, Error::SampleOffsetOutOfRange { index: 0, .. }), "{err:?}");
+    }
+}
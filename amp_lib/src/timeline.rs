@@ -0,0 +1,232 @@
+use crate::bank::*;
+use crate::song::*;
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+/// Gets paths to .bnk and .nse files relative to midi
+pub fn get_bank_paths<T: AsRef<Path>>(mid_path: T, bank_name: &str) -> (PathBuf, PathBuf) {
+    let bank_path = mid_path
+        .as_ref()
+        .parent()
+        .map(|p| p.join(bank_name))
+        .unwrap_or_else(|| PathBuf::from(bank_name));
+
+    let sample_path = bank_path.with_extension("nse");
+
+    (bank_path, sample_path)
+}
+
+/// Bank referenced by song, loaded with paths to its .bnk and .nse
+#[derive(Debug)]
+pub struct TimelineBank {
+    pub name: String,
+    pub bank_path: PathBuf,
+    pub sample_path: PathBuf,
+    pub bank: BankFile,
+}
+
+impl TimelineBank {
+    /// Loads .bnk named in song relative to midi, samples are measured now so missing .nse is found before rendering
+    pub fn load<T: AsRef<Path>>(mid_path: T, bank_name: &str) -> Result<Self, Error> {
+        let (bank_path, sample_path) = get_bank_paths(mid_path, bank_name);
+
+        let mut bank = BankFile::from_file(&bank_path)?;
+        bank.measure_samples(&mut std::fs::File::open(&sample_path)?)?;
+
+        Ok(Self {
+            name: bank_name.to_owned(),
+            bank_path,
+            sample_path,
+            bank,
+        })
+    }
+
+    pub fn open_sample_file(&self) -> Result<std::fs::File, Error> {
+        Ok(std::fs::File::open(&self.sample_path)?)
+    }
+}
+
+/// Span of song where bank is used
+#[derive(Clone, Debug)]
+pub struct BankSegment {
+    pub start_secs: f64,
+    pub end_secs: Option<f64>, // None if used until end of song
+    pub bank_index: usize,
+}
+
+/// Banks used by song over time, from text events in BANK track
+///
+/// Each referenced bank is loaded once, even if song switches back to it.
+/// First bank is also used for anything before first switch.
+#[derive(Debug)]
+pub struct BankTimeline {
+    pub banks: Vec<TimelineBank>,
+    pub segments: Vec<BankSegment>,
+}
+
+impl BankTimeline {
+    /// Loads banks of song, paths are relative to midi
+    pub fn from_song<T: AsRef<Path>>(song: &AmpSong, mid_path: T) -> Result<Self, Error> {
+        let mid_path = mid_path.as_ref();
+        let mut banks: Vec<TimelineBank> = Vec::new();
+
+        for switch in sorted_switches(song) {
+            if !banks.iter().any(|b| b.name.eq(&switch.bank_name)) {
+                banks.push(TimelineBank::load(mid_path, &switch.bank_name)?);
+            }
+        }
+
+        Ok(Self::from_banks(song, banks))
+    }
+
+    /// Builds segments from bank switches of song, matching banks by name
+    ///
+    /// Switches to banks not given are ignored.
+    pub fn from_banks(song: &AmpSong, banks: Vec<TimelineBank>) -> Self {
+        let mut segments: Vec<BankSegment> = Vec::new();
+
+        for switch in sorted_switches(song) {
+            let Some(bank_index) = banks.iter().position(|b| b.name.eq(&switch.bank_name)) else {
+                continue;
+            };
+
+            // Switching to same bank doesn't start new segment
+            if segments.last().is_some_and(|s| s.bank_index == bank_index) {
+                continue;
+            }
+
+            if let Some(last) = segments.last_mut() {
+                last.end_secs = Some(switch.pos_secs);
+            }
+
+            segments.push(BankSegment {
+                start_secs: switch.pos_secs,
+                end_secs: None,
+                bank_index,
+            });
+        }
+
+        Self {
+            banks,
+            segments,
+        }
+    }
+
+    /// Segment playing at time, first segment if before any switch
+    pub fn segment_at(&self, secs: f64) -> Option<&BankSegment> {
+        self.segments
+            .iter()
+            .rev()
+            .find(|s| s.start_secs <= secs)
+            .or_else(|| self.segments.first())
+    }
+
+    /// Index of bank playing at time
+    pub fn bank_index_at(&self, secs: f64) -> usize {
+        self.segment_at(secs)
+            .map(|s| s.bank_index)
+            .unwrap_or_default()
+    }
+
+    pub fn bank_at(&self, secs: f64) -> Option<&TimelineBank> {
+        self.banks.get(self.bank_index_at(secs))
+    }
+}
+
+fn sorted_switches(song: &AmpSong) -> Vec<&BankSwitch> {
+    let mut bank_switches = song.bank_switches.iter().collect::<Vec<_>>();
+    bank_switches.sort_by(|a, b| a.pos_secs.total_cmp(&b.pos_secs));
+
+    bank_switches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::song::tests::*;
+
+    fn bank_event(delta: &[u8], bank_name: &str) -> Vec<u8> {
+        meta(delta, 0x01, bank_name.as_bytes())
+    }
+
+    // 120 bpm, so 480 ticks is 0.5s
+    fn test_song(name: &str) -> AmpSong {
+        let bank = [
+            bank_event(&[0x83, 0x60], "a.bnk"), // 0.5s
+            bank_event(&[0x83, 0x60], "b.bnk"), // 1.0s
+            bank_event(&[0x87, 0x40], "a.bnk"), // 2.0s
+            bank_event(&[0x83, 0x60], "a.bnk"), // 2.5s
+        ].concat();
+
+        let tracks = [
+            named_track("BANK", &bank),
+            named_track("lead", &[0x00, 0x90, 60, 100, 0x83, 0x60, 0x80, 60, 0]),
+        ];
+
+        read_song(&format!("timeline_{name}"), &tracks).unwrap()
+    }
+
+    fn timeline_bank(name: &str) -> TimelineBank {
+        TimelineBank {
+            name: name.to_owned(),
+            bank_path: PathBuf::from(name),
+            sample_path: PathBuf::from(name).with_extension("nse"),
+            bank: BankFile::default(),
+        }
+    }
+
+    fn bank_name_at(timeline: &BankTimeline, secs: f64) -> &str {
+        timeline.bank_at(secs).map(|b| b.name.as_str()).unwrap()
+    }
+
+    #[test]
+    fn switching_back_reuses_bank() {
+        let timeline = BankTimeline::from_banks(&test_song("switch_back"), vec![timeline_bank("a.bnk"), timeline_bank("b.bnk")]);
+
+        let segments = timeline.segments
+            .iter()
+            .map(|s| (s.start_secs, s.end_secs, s.bank_index))
+            .collect::<Vec<_>>();
+
+        // Repeated switch to same bank is part of last segment
+        assert_eq!(segments, [(0.5, Some(1.0), 0), (1.0, Some(2.0), 1), (2.0, None, 0)]);
+
+        assert_eq!(bank_name_at(&timeline, 1.5), "b.bnk");
+        assert_eq!(bank_name_at(&timeline, 1.999), "b.bnk");
+        assert_eq!(bank_name_at(&timeline, 2.0), "a.bnk");
+    }
+
+    #[test]
+    fn first_bank_plays_before_first_switch() {
+        let timeline = BankTimeline::from_banks(&test_song("before_first"), vec![timeline_bank("a.bnk"), timeline_bank("b.bnk")]);
+
+        assert_eq!(timeline.segment_at(0.).map(|s| s.start_secs), Some(0.5));
+        assert_eq!(timeline.bank_index_at(0.25), 0);
+        assert_eq!(bank_name_at(&timeline, 0.), "a.bnk");
+    }
+
+    #[test]
+    fn last_segment_plays_until_end() {
+        let timeline = BankTimeline::from_banks(&test_song("last_segment"), vec![timeline_bank("a.bnk"), timeline_bank("b.bnk")]);
+
+        let last = timeline.segment_at(1000.).unwrap();
+        assert_eq!((last.start_secs, last.end_secs, last.bank_index), (2.0, None, 0));
+    }
+
+    #[test]
+    fn switches_to_missing_banks_are_ignored() {
+        let timeline = BankTimeline::from_banks(&test_song("missing"), vec![timeline_bank("b.bnk")]);
+
+        let segments = timeline.segments
+            .iter()
+            .map(|s| (s.start_secs, s.end_secs, s.bank_index))
+            .collect::<Vec<_>>();
+
+        assert_eq!(segments, [(1.0, None, 0)]);
+        assert_eq!(bank_name_at(&timeline, 0.), "b.bnk");
+
+        let empty = BankTimeline::from_banks(&test_song("missing_empty"), Vec::new());
+        assert!(empty.segment_at(1.).is_none());
+        assert!(empty.bank_at(1.).is_none());
+    }
+}
@@ -0,0 +1,257 @@
+pub const VAG_BYTES_PER_BLOCK: usize = 16;
+pub const VAG_SAMPLES_PER_BLOCK: usize = 28;
+
+// Block flags
+pub const VAG_FLAG_LOOP_END: u8 = 0x01;
+pub const VAG_FLAG_LOOP_REPEAT: u8 = 0x02;
+pub const VAG_FLAG_LOOP_START: u8 = 0x04;
+pub const VAG_FLAG_STREAM_END: u8 = 0x07;
+
+// Prediction filters used by SPU2 (coefficients / 64)
+const VAG_FILTERS: [(i32, i32); 5] = [
+    (0, 0),
+    (60, 0),
+    (115, -52),
+    (98, -55),
+    (122, -60),
+];
+
+const VAG_MAX_SHIFT: u8 = 12;
+
+/// Encodes 16-bit pcm to PS2 ADPCM blocks, keeping decoder history between blocks
+#[derive(Debug, Default)]
+pub struct VagEncoder {
+    hist1: i32,
+    hist2: i32,
+}
+
+struct BlockCandidate {
+    filter: u8,
+    shift: u8,
+    nibbles: [u8; VAG_SAMPLES_PER_BLOCK],
+    hist: (i32, i32),
+    error: i64,
+}
+
+impl VagEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodes up to 28 samples into block, missing samples are treated as silence
+    ///
+    /// Loop start blocks only use filter 0, since decoder history when jumping back is from loop end
+    pub fn encode_block(&mut self, samples: &[i16], flags: u8) -> [u8; VAG_BYTES_PER_BLOCK] {
+        let mut input = [0i16; VAG_SAMPLES_PER_BLOCK];
+        for (i, s) in input.iter_mut().zip(samples.iter()) {
+            *i = *s;
+        }
+
+        let filter_count = match flags & VAG_FLAG_LOOP_START {
+            0 => VAG_FILTERS.len() as u8,
+            _ => 1,
+        };
+
+        // Try every filter and shift, keep the one closest to input once decoded
+        let best = (0..filter_count)
+            .flat_map(|filter| (0..=VAG_MAX_SHIFT).map(move |shift| (filter, shift)))
+            .map(|(filter, shift)| self.try_block(&input, filter, shift))
+            .min_by_key(|c| c.error)
+            .unwrap();
+
+        self.hist1 = best.hist.0;
+        self.hist2 = best.hist.1;
+
+        let mut block = [0u8; VAG_BYTES_PER_BLOCK];
+        block[0] = (best.filter << 4) | best.shift;
+        block[1] = flags;
+
+        for (i, pair) in best.nibbles.chunks(2).enumerate() {
+            block[2 + i] = (pair[0] & 0xF) | ((pair[1] & 0xF) << 4);
+        }
+
+        block
+    }
+
+    fn try_block(&self, input: &[i16; VAG_SAMPLES_PER_BLOCK], filter: u8, shift: u8) -> BlockCandidate {
+        let (c1, c2) = VAG_FILTERS[filter as usize];
+        let (mut hist1, mut hist2) = (self.hist1, self.hist2);
+
+        let mut nibbles = [0u8; VAG_SAMPLES_PER_BLOCK];
+        let mut error = 0i64;
+
+        for (i, sample) in input.iter().enumerate() {
+            let predicted = (hist1 * c1 + hist2 * c2 + 32) >> 6;
+            let residual = *sample as i32 - predicted;
+
+            // Quantize residual to 4 bits, rounding to nearest
+            let scaled = ((residual << shift) as f32 / 4096.).round() as i32;
+            let nibble = scaled.clamp(-8, 7);
+
+            let decoded = (((nibble << 12) >> shift) + predicted).clamp(i16::MIN as i32, i16::MAX as i32);
+
+            let diff = (*sample as i32 - decoded) as i64;
+            error += diff * diff;
+
+            nibbles[i] = nibble as u8 & 0xF;
+            hist2 = hist1;
+            hist1 = decoded;
+        }
+
+        BlockCandidate {
+            filter,
+            shift,
+            nibbles,
+            hist: (hist1, hist2),
+            error,
+        }
+    }
+}
+
+/// Encodes pcm to vag stream, ending with end block (0x07)
+///
+/// Loop points are in sample frames (end exclusive) and get aligned to 28 sample blocks.
+/// Samples after loop end are dropped since playback never reaches them.
+pub fn encode_vag(pcm: &[i16], loop_points: Option<(u32, u32)>) -> Vec<u8> {
+    let mut block_count = pcm.len().div_ceil(VAG_SAMPLES_PER_BLOCK).max(1);
+
+    let loop_blocks = loop_points
+        .filter(|(start, end)| start < end && (*start as usize) < pcm.len())
+        .map(|(start, end)| {
+            let start_block = start as usize / VAG_SAMPLES_PER_BLOCK;
+            let end_block = (end as usize).div_ceil(VAG_SAMPLES_PER_BLOCK).min(block_count) - 1;
+
+            // Loop needs separate start and end blocks since 0x07 marks end of stream
+            (start_block, end_block.max(start_block + 1))
+        });
+
+    if let Some((_, end_block)) = loop_blocks {
+        block_count = end_block + 1;
+    }
+
+    let mut encoder = VagEncoder::new();
+    let mut data = Vec::with_capacity((block_count + 1) * VAG_BYTES_PER_BLOCK);
+
+    for i in 0..block_count {
+        let flags = match loop_blocks {
+            Some((_, end)) if i == end => VAG_FLAG_LOOP_REPEAT | VAG_FLAG_LOOP_END,
+            Some((start, _)) if i == start => VAG_FLAG_LOOP_START | VAG_FLAG_LOOP_REPEAT,
+            Some((start, _)) if i > start => VAG_FLAG_LOOP_REPEAT,
+            None if i == block_count - 1 => VAG_FLAG_LOOP_END,
+            _ => 0,
+        };
+
+        let start = (i * VAG_SAMPLES_PER_BLOCK).min(pcm.len());
+        let end = (start + VAG_SAMPLES_PER_BLOCK).min(pcm.len());
+
+        data.extend(encoder.encode_block(&pcm[start..end], flags));
+    }
+
+    // End of stream marker
+    let mut end_block = [0u8; VAG_BYTES_PER_BLOCK];
+    end_block[1] = VAG_FLAG_STREAM_END;
+    data.extend(end_block);
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grim::audio::VAGDecoder;
+
+    fn decode_vag(data: &[u8]) -> Vec<i16> {
+        let mut decoder = VAGDecoder::new();
+
+        data.chunks_exact(VAG_BYTES_PER_BLOCK)
+            .take_while(|b| b[1] != VAG_FLAG_STREAM_END)
+            .flat_map(|b| decoder.decode_block(b))
+            .collect()
+    }
+
+    fn snr_db(input: &[i16], output: &[i16]) -> f64 {
+        let (signal, noise) = input
+            .iter()
+            .zip(output.iter())
+            .fold((0f64, 0f64), |(signal, noise), (i, o)| {
+                let diff = *i as f64 - *o as f64;
+                (signal + (*i as f64).powi(2), noise + diff * diff)
+            });
+
+        10. * (signal / noise.max(1.)).log10()
+    }
+
+    fn block_flags(data: &[u8]) -> Vec<u8> {
+        data.chunks_exact(VAG_BYTES_PER_BLOCK)
+            .map(|b| b[1])
+            .collect()
+    }
+
+    fn sine(len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| ((i as f64 * 440. * std::f64::consts::TAU / 44100.).sin() * 20000.) as i16)
+            .collect()
+    }
+
+    fn noise(len: usize) -> Vec<i16> {
+        // Fixed seed LCG so test is repeatable
+        let mut state = 0x1234_5678u32;
+
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((state >> 16) as i16) / 2
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sine_round_trips_above_snr_floor() {
+        let pcm = sine(VAG_SAMPLES_PER_BLOCK * 200);
+        let decoded = decode_vag(&encode_vag(&pcm, None));
+
+        assert_eq!(decoded.len(), pcm.len());
+
+        let snr = snr_db(&pcm, &decoded);
+        assert!(snr > 45., "sine snr {snr:.1} dB");
+    }
+
+    #[test]
+    fn noise_round_trips_above_snr_floor() {
+        let pcm = noise(VAG_SAMPLES_PER_BLOCK * 200);
+        let decoded = decode_vag(&encode_vag(&pcm, None));
+
+        assert_eq!(decoded.len(), pcm.len());
+
+        let snr = snr_db(&pcm, &decoded);
+        assert!(snr > 18., "noise snr {snr:.1} dB");
+    }
+
+    #[test]
+    fn one_shot_ends_with_end_flag_and_stream_end_block() {
+        let data = encode_vag(&sine(VAG_SAMPLES_PER_BLOCK * 4), None);
+
+        assert_eq!(block_flags(&data), [0x00, 0x00, 0x00, 0x01, 0x07]);
+    }
+
+    #[test]
+    fn loop_sets_start_repeat_and_end_flags() {
+        let pcm = sine(VAG_SAMPLES_PER_BLOCK * 8);
+        let loop_points = ((VAG_SAMPLES_PER_BLOCK * 2) as u32, (VAG_SAMPLES_PER_BLOCK * 6) as u32);
+        let data = encode_vag(&pcm, Some(loop_points));
+
+        // Blocks after loop end are dropped
+        assert_eq!(block_flags(&data), [0x00, 0x00, 0x06, 0x02, 0x02, 0x03, 0x07]);
+
+        // Loop start block can't depend on decoder history
+        assert_eq!(data[2 * VAG_BYTES_PER_BLOCK] >> 4, 0);
+    }
+
+    #[test]
+    fn unaligned_loop_is_aligned_to_blocks() {
+        let pcm = sine(VAG_SAMPLES_PER_BLOCK * 8);
+        let data = encode_vag(&pcm, Some((30, 100)));
+
+        assert_eq!(block_flags(&data), [0x00, 0x06, 0x02, 0x03, 0x07]);
+    }
+}
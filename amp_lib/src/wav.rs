@@ -0,0 +1,286 @@
+use crate::Error;
+use std::io::Write;
+use std::path::Path;
+
+const MIDI_UNITY_NOTE: u32 = 60;
+
+/// Writes 16-bit pcm .wav files, with optional loop stored in RIFF smpl chunk
+pub struct WavWriter<'a> {
+    samples: &'a [i16],
+    channels: u16,
+    sample_rate: u32,
+    loop_points: Option<(u32, u32)>,
+}
+
+impl<'a> WavWriter<'a> {
+    pub fn new(samples: &'a [i16], channels: u16, sample_rate: u32) -> Self {
+        Self {
+            samples,
+            channels,
+            sample_rate,
+            loop_points: None,
+        }
+    }
+
+    /// Sets loop using frame positions (end is exclusive)
+    pub fn with_loop(mut self, start: u32, end: u32) -> Self {
+        self.loop_points = Some((start, end)).filter(|(s, e)| s < e);
+        self
+    }
+
+    pub fn encode_to_file<T: AsRef<Path>>(&self, path: T) -> Result<(), Error> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.encode(&mut file)?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    pub fn encode<T: Write>(&self, writer: &mut T) -> Result<(), Error> {
+        let channels = self.channels.max(1);
+        let block_align = channels as u32 * 2;
+        let data_size = self.samples.len() as u32 * 2;
+        let smpl_size = self.loop_points.map(|_| 8 + 36 + 24).unwrap_or_default();
+
+        // RIFF header
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(4 + (8 + 16) + (8 + data_size) + smpl_size).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        // Format
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * block_align).to_le_bytes())?;
+        writer.write_all(&(block_align as u16).to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?; // Bits per sample
+
+        // Data
+        writer.write_all(b"data")?;
+        writer.write_all(&data_size.to_le_bytes())?;
+
+        let data = self.samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        writer.write_all(&data)?;
+
+        // Sampler info with single forward loop
+        if let Some((start, end)) = self.loop_points {
+            let sample_period = 1_000_000_000 / self.sample_rate.max(1);
+
+            let smpl = [
+                0, // Manufacturer
+                0, // Product
+                sample_period,
+                MIDI_UNITY_NOTE,
+                0, // Pitch fraction
+                0, // SMPTE format
+                0, // SMPTE offset
+                1, // Loop count
+                0, // Sampler data size
+                0, // Cue point id
+                0, // Loop type (forward)
+                start,
+                end - 1, // Inclusive
+                0, // Fraction
+                0, // Play count (infinite)
+            ];
+
+            writer.write_all(b"smpl")?;
+            writer.write_all(&60u32.to_le_bytes())?; // 36 bytes + 24 per loop
+
+            for value in smpl {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WavData {
+    pub samples: Vec<i16>, // Interleaved
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub loop_points: Option<(u32, u32)>, // Frames, end exclusive
+}
+
+impl WavData {
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let data = std::fs::read(path)?;
+        Self::from_bytes(&data)
+    }
+
+    /// Reads pcm .wav (8, 16 or 24-bit) along with first loop in smpl chunk
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err(Error::Wav("Not a RIFF WAVE file".into()));
+        }
+
+        let mut wav = WavData::default();
+        let mut bits_per_sample = 0;
+        let mut pcm_data = None;
+
+        let mut offset = 12;
+
+        while offset + 8 <= data.len() {
+            let tag = &data[offset..(offset + 4)];
+            let size = u32::from_le_bytes(data[(offset + 4)..(offset + 8)].try_into().unwrap()) as usize;
+
+            let chunk_start = offset + 8;
+            let chunk_end = (chunk_start + size).min(data.len());
+            let chunk = &data[chunk_start..chunk_end];
+
+            match tag {
+                b"fmt " if chunk.len() >= 16 => {
+                    let format = u16::from_le_bytes([chunk[0], chunk[1]]);
+
+                    // PCM or extensible
+                    if format != 1 && format != 0xFFFE {
+                        return Err(Error::Wav(format!("Unsupported wav format {format}, only pcm is supported")));
+                    }
+
+                    wav.channels = u16::from_le_bytes([chunk[2], chunk[3]]);
+                    wav.sample_rate = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+                    bits_per_sample = u16::from_le_bytes([chunk[14], chunk[15]]);
+                },
+                b"data" => {
+                    pcm_data = Some(chunk);
+                },
+                b"smpl" if chunk.len() >= 60 => {
+                    let loop_count = u32::from_le_bytes(chunk[28..32].try_into().unwrap());
+
+                    if loop_count > 0 {
+                        let start = u32::from_le_bytes(chunk[44..48].try_into().unwrap());
+                        let end = u32::from_le_bytes(chunk[48..52].try_into().unwrap());
+
+                        wav.loop_points = Some((start, end + 1)).filter(|(s, e)| s < e);
+                    }
+                },
+                _ => {}
+            }
+
+            // Chunks are word aligned
+            offset = chunk_start + size + (size & 1);
+        }
+
+        let Some(pcm_data) = pcm_data else {
+            return Err(Error::Wav("Missing data chunk".into()));
+        };
+
+        if wav.channels == 0 {
+            return Err(Error::Wav("Missing fmt chunk".into()));
+        }
+
+        wav.samples = match bits_per_sample {
+            8 => pcm_data
+                .iter()
+                .map(|b| ((*b as i16) - 128) << 8)
+                .collect(),
+            16 => pcm_data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            24 => pcm_data
+                .chunks_exact(3)
+                .map(|b| i16::from_le_bytes([b[1], b[2]]))
+                .collect(),
+            _ => return Err(Error::Wav(format!("Unsupported bits per sample {bits_per_sample}"))),
+        };
+
+        Ok(wav)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(samples: &[i16], channels: u16, loop_points: Option<(u32, u32)>) -> Vec<u8> {
+        let writer = WavWriter::new(samples, channels, 22050);
+
+        let writer = match loop_points {
+            Some((start, end)) => writer.with_loop(start, end),
+            None => writer,
+        };
+
+        let mut data = Vec::new();
+        writer.encode(&mut data).unwrap();
+        data
+    }
+
+    // Same wav with data chunk stored using different bits per sample
+    fn with_bits_per_sample(wav_data: &[u8], bits: u16) -> Vec<u8> {
+        let data_size = u32::from_le_bytes(wav_data[40..44].try_into().unwrap()) as usize;
+        let samples = wav_data[44..(44 + data_size)].chunks_exact(2);
+
+        let pcm = match bits {
+            8 => samples.map(|b| vec![(b[1] as i8 as i16 + 128) as u8]).collect::<Vec<_>>(),
+            _ => samples.map(|b| vec![0, b[0], b[1]]).collect::<Vec<_>>(),
+        }.concat();
+
+        let mut fmt = wav_data[20..36].to_vec();
+        fmt[14..16].copy_from_slice(&bits.to_le_bytes());
+
+        let rest = &wav_data[(44 + data_size)..];
+        let riff_size = 4 + (8 + 16) + (8 + pcm.len()) + rest.len();
+
+        [
+            b"RIFF".as_slice(), &(riff_size as u32).to_le_bytes(), b"WAVE",
+            b"fmt ", &16u32.to_le_bytes(), &fmt,
+            b"data", &(pcm.len() as u32).to_le_bytes(), &pcm,
+            rest,
+        ].concat()
+    }
+
+    #[test]
+    fn written_loop_reads_back_exactly() {
+        let samples = (0..40).map(|i| (i - 20) * 256).collect::<Vec<i16>>();
+        let data = encode(&samples, 2, Some((3, 10)));
+
+        // smpl end is inclusive
+        let smpl = &data[(data.len() - 68)..];
+        assert_eq!(&smpl[0..4], b"smpl");
+        assert_eq!(u32::from_le_bytes(smpl[52..56].try_into().unwrap()), 3);
+        assert_eq!(u32::from_le_bytes(smpl[56..60].try_into().unwrap()), 9);
+
+        let wav = WavData::from_bytes(&data).unwrap();
+
+        assert_eq!(wav.samples, samples);
+        assert_eq!(wav.channels, 2);
+        assert_eq!(wav.sample_rate, 22050);
+        assert_eq!(wav.frame_count(), 20);
+        assert_eq!(wav.loop_points, Some((3, 10)));
+    }
+
+    #[test]
+    fn empty_loop_is_not_written() {
+        let data = encode(&[0; 8], 1, Some((5, 5)));
+        let wav = WavData::from_bytes(&data).unwrap();
+
+        assert_eq!(data.len(), 44 + 16);
+        assert_eq!(wav.loop_points, None);
+    }
+
+    #[test]
+    fn reads_8_and_24_bit_with_loop() {
+        let samples = (0..16).map(|i| (i - 8) * 4096).collect::<Vec<i16>>();
+        let data = encode(&samples, 1, Some((0, 16)));
+
+        for bits in [8, 24] {
+            let wav = WavData::from_bytes(&with_bits_per_sample(&data, bits)).unwrap();
+
+            assert_eq!(wav.samples, samples, "{bits}-bit");
+            assert_eq!(wav.loop_points, Some((0, 16)), "{bits}-bit");
+        }
+    }
+}
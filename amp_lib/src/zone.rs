@@ -0,0 +1,234 @@
+use crate::bank::*;
+use std::ops::Range;
+
+/// Sample zone matched for a note, with everything needed for playback
+#[derive(Debug)]
+pub struct KeyZone<'a> {
+    pub sdes_index: usize,
+    pub sdes: &'a SdesEntry,
+    pub sample_index: usize,
+    pub sample: &'a SampleEntry,
+    pub playback_rate: f32, // Ratio to sample rate of sample
+    pub gain: f32, // 0.0-1.0, zone volume scaled by velocity
+    pub pan: f32, // -1.0 (left) to 1.0 (right)
+}
+
+impl BankFile {
+    /// Range of insts owned by bank (banks own insts consecutively, `inst_count` per bank)
+    pub fn bank_inst_range(&self, bank_index: usize) -> Range<usize> {
+        let start = self.banks
+            .iter()
+            .take(bank_index)
+            .map(|b| b.inst_count as usize)
+            .sum::<usize>();
+
+        let count = self.banks
+            .get(bank_index)
+            .map(|b| b.inst_count as usize)
+            .unwrap_or_default();
+
+        start.min(self.insts.len())..(start + count).min(self.insts.len())
+    }
+
+    /// Range of sdes entries owned by inst
+    ///
+    /// `InstEntry::sdes` is the number of zones in inst, insts own sdes consecutively in order
+    /// like banks own insts with `inst_count`. Written the same way by `BankBuilder::add_zone`.
+    pub fn inst_sdes_range(&self, inst_index: usize) -> Range<usize> {
+        let start = self.insts
+            .iter()
+            .take(inst_index)
+            .map(|i| i.sdes as usize)
+            .sum::<usize>();
+
+        let count = self.insts
+            .get(inst_index)
+            .map(|i| i.sdes as usize)
+            .unwrap_or_default();
+
+        start.min(self.sdes.len())..(start + count).min(self.sdes.len())
+    }
+
+    /// First bank entry with inst playing program, or first bank entry if none have it
+    pub fn find_prog_bank(&self, prog: u16) -> Option<&BankEntry> {
+        self.banks
+            .iter()
+            .enumerate()
+            .find(|(i, _)| self.bank_inst_range(*i).any(|inst| self.insts[inst].prog == prog))
+            .map(|(_, b)| b)
+            .or_else(|| self.banks.first())
+    }
+
+    /// Finds sample zones to play for note
+    pub fn resolve(&self, bank_num: u8, prog: u16, note: u8, velocity: u8) -> Vec<KeyZone<'_>> {
+        let Some(bank_index) = self.banks.iter().position(|b| b.bank_num == bank_num) else {
+            return Vec::new();
+        };
+
+        let Some(inst_index) = self.bank_inst_range(bank_index).find(|i| self.insts[*i].prog == prog) else {
+            return Vec::new();
+        };
+
+        self.inst_sdes_range(inst_index)
+            .filter(|i| (self.sdes[*i].min_pitch..=self.sdes[*i].max_pitch).contains(&note))
+            .filter_map(|sdes_index| {
+                let sdes = &self.sdes[sdes_index];
+                let sample_index = sdes.samp as usize;
+                let sample = self.samples.get(sample_index)?;
+
+                Some(KeyZone {
+                    sdes_index,
+                    sdes,
+                    sample_index,
+                    sample,
+                    playback_rate: sdes.playback_rate(note),
+                    gain: (sdes.vol.min(127) as f32 / 127.) * (velocity.min(127) as f32 / 127.),
+                    pan: sdes.pan_position(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl SdesEntry {
+    /// Semitones note is shifted from recorded pitch of sample
+    pub fn pitch_offset(&self, note: u8) -> f32 {
+        note as f32 - self.base_pitch as f32 + (self.transpose as i8) as f32
+    }
+
+    /// Playback rate relative to sample rate of sample
+    pub fn playback_rate(&self, note: u8) -> f32 {
+        2f32.powf(self.pitch_offset(note) / 12.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(min_pitch: u8, max_pitch: u8, samp: u8) -> SdesEntry {
+        SdesEntry {
+            min_pitch,
+            max_pitch,
+            base_pitch: 60,
+            vol: 127,
+            samp,
+            ..Default::default()
+        }
+    }
+
+    fn test_bank(inst_zones: &[Vec<SdesEntry>]) -> BankFile {
+        let mut bank = BankFile::default();
+
+        bank.banks.push(BankEntry {
+            bank_num: 2,
+            inst_count: inst_zones.len() as u8,
+            ..Default::default()
+        });
+
+        for (i, zones) in inst_zones.iter().enumerate() {
+            bank.insts.push(InstEntry {
+                prog: i as u16,
+                sdes: zones.len() as u16,
+                ..Default::default()
+            });
+        }
+
+        bank.sdes = inst_zones
+            .iter()
+            .flat_map(|zones| zones.iter().map(|z| zone(z.min_pitch, z.max_pitch, z.samp)))
+            .collect();
+
+        bank.samples = (0..4)
+            .map(|_| SampleEntry {
+                channels: 1,
+                sample_rate: 22050,
+                ..Default::default()
+            })
+            .collect();
+
+        bank
+    }
+
+    fn resolved_samples(bank: &BankFile, prog: u16, note: u8) -> Vec<usize> {
+        bank.resolve(2, prog, note, 127)
+            .iter()
+            .map(|z| z.sample_index)
+            .collect()
+    }
+
+    #[test]
+    fn multi_zone_insts_own_consecutive_sdes() {
+        let bank = test_bank(&[
+            vec![zone(0, 59, 0), zone(60, 127, 1)],
+            vec![zone(0, 127, 2)],
+            vec![zone(0, 63, 3), zone(64, 127, 0), zone(32, 95, 1)],
+        ]);
+
+        assert_eq!(bank.inst_sdes_range(0), 0..2);
+        assert_eq!(bank.inst_sdes_range(1), 2..3);
+        assert_eq!(bank.inst_sdes_range(2), 3..6);
+
+        assert_eq!(resolved_samples(&bank, 0, 40), [0]);
+        assert_eq!(resolved_samples(&bank, 0, 60), [1]);
+        assert_eq!(resolved_samples(&bank, 1, 100), [2]);
+        assert_eq!(resolved_samples(&bank, 2, 40), [3, 1]);
+    }
+
+    #[test]
+    fn single_zone_insts_own_one_sdes_each() {
+        let bank = test_bank(&[
+            vec![zone(0, 127, 0)],
+            vec![zone(0, 127, 1)],
+            vec![zone(0, 127, 2)],
+        ]);
+
+        // Counts of [1, 1, 1] also sum to sdes count, must not be read as start indices
+        for i in 0..3 {
+            assert_eq!(bank.inst_sdes_range(i), i..(i + 1));
+            assert_eq!(resolved_samples(&bank, i as u16, 60), [i]);
+        }
+    }
+
+    #[test]
+    fn empty_inst_owns_no_sdes() {
+        let bank = test_bank(&[
+            vec![],
+            vec![zone(0, 127, 0)],
+            vec![zone(0, 63, 1), zone(64, 127, 2)],
+        ]);
+
+        assert_eq!(bank.inst_sdes_range(0), 0..0);
+        assert_eq!(bank.inst_sdes_range(1), 0..1);
+        assert_eq!(bank.inst_sdes_range(2), 1..3);
+        assert!(resolved_samples(&bank, 0, 60).is_empty());
+    }
+
+    #[test]
+    fn finds_bank_entry_with_program() {
+        let mut bank = test_bank(&[
+            vec![zone(0, 127, 0)],
+            vec![zone(0, 127, 1)],
+        ]);
+
+        // Second bank entry owns third inst with program 5
+        bank.banks.push(BankEntry {
+            bank_num: 7,
+            inst_count: 1,
+            ..Default::default()
+        });
+
+        bank.insts.push(InstEntry {
+            prog: 5,
+            sdes: 1,
+            ..Default::default()
+        });
+
+        bank.sdes.push(zone(0, 127, 2));
+
+        assert_eq!(bank.find_prog_bank(1).map(|b| b.bank_num), Some(2));
+        assert_eq!(bank.find_prog_bank(5).map(|b| b.bank_num), Some(7));
+        assert_eq!(bank.find_prog_bank(9).map(|b| b.bank_num), Some(2));
+        assert_eq!(bank.resolve(7, 5, 60, 127)[0].sample_index, 2);
+    }
+}